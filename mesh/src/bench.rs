@@ -0,0 +1,182 @@
+use std::time::Duration;
+
+use crate::config::Config;
+use crate::geometry::CullMode;
+use crate::geometry::Mesh;
+use crate::geometry::Tri;
+use crate::geometry::Vert;
+use crate::geometry::CREASE_ANGLE;
+use crate::math::Vec2f;
+use crate::math::Vec3f;
+use crate::render_utils::Buffer;
+use crate::render_utils::Camera;
+use crate::render_utils::Color;
+use crate::render_utils::Scene;
+use crate::renderer::RenderSettings;
+use crate::renderer::Renderer;
+use crate::renderer::StageTimes;
+use crate::utils::make_camera;
+use crate::utils::make_scene;
+use crate::utils::MAIN_OBJECT;
+use crate::Float;
+use crate::TAU;
+
+/// all 16:9, which the synthetic scenes are built for
+const RESOLUTIONS: [(usize, usize); 3] = [(640, 360), (1280, 720), (2560, 1440)];
+/// thrown away so the caches and the allocator settle
+const WARMUP: usize = 2;
+
+/// the synthetic scenes ignore --fov so their timings stay comparable between runs
+const SYNTHETIC_FOV: Float = 90.;
+/// at `SYNTHETIC_FOV` the view reaches this far either side of the middle at this distance
+const DISTANCE: Float = 10.;
+const TINY_COLUMNS: usize = 320;
+const TINY_ROWS: usize = 180;
+const HUGE_SIDES: usize = 8;
+const OVERDRAW_LAYERS: usize = 8;
+
+struct BenchScene {
+    name: &'static str,
+    scene: Scene,
+    camera: Camera,
+    fov: Float,
+}
+
+/// prints the median of each stage, rendered with the same settings and threads as the viewer
+pub fn run_benchmarks(config: &Config, settings: RenderSettings, frames: usize) {
+    let mut model = make_scene(config);
+    if let Some(mesh) = model.get_mut(MAIN_OBJECT) {
+        mesh.cull = config.cull;
+        if config.flip_winding {
+            mesh.flip_winding();
+        }
+    }
+    let camera = make_camera(config, &model);
+    let mut scenes = vec![
+        BenchScene { name: "model", scene: model, camera, fov: config.fov },
+        synthetic("tiny", tiny_tris()),
+        synthetic("huge", huge_tris()),
+        synthetic("overdraw", overdraw_layers()),
+    ];
+    if settings.shadows {
+        scenes.iter_mut().for_each(|bench| bench.scene.update_shadows());
+    }
+
+    println!(
+        "{:<10}{:>11}{:>9}{:>9}{:>12}{:>12}{:>12}{:>12}",
+        "scene", "resolution", "tris", "polys", "transform", "setup", "fill", "total"
+    );
+    for bench in &scenes {
+        for (width, height) in RESOLUTIONS {
+            let mut buffer = Buffer::cons_antialiased(height, width, config.antialias);
+            buffer.set_background(config.background);
+            let mut runs = Vec::new();
+            for frame in 0..WARMUP + frames {
+                buffer.clear();
+                let mut renderer = Renderer::cons(&mut buffer, &bench.scene, &bench.camera, bench.fov, settings);
+                renderer.render_scene();
+                let times = renderer.stage_times();
+                buffer.resolve();
+                if frame >= WARMUP {
+                    runs.push(times);
+                }
+            }
+            println!(
+                "{:<10}{:>11}{:>9}{:>9}{:>12}{:>12}{:>12}{:>12}",
+                bench.name,
+                format!("{}x{}", width, height),
+                runs[0].tris,
+                runs[0].polys,
+                median(&runs, |times| times.transform),
+                median(&runs, |times| times.setup),
+                median(&runs, |times| times.fill),
+                median(&runs, |times| times.total()),
+            );
+        }
+    }
+}
+
+/// in milliseconds, already formatted for the table
+fn median(runs: &[StageTimes], stage: impl Fn(&StageTimes) -> Duration) -> String {
+    let mut times: Vec<Duration> = runs.iter().map(stage).collect();
+    times.sort();
+    format!("{:.3} ms", times[times.len() / 2].as_secs_f64() * 1000.)
+}
+
+fn synthetic(name: &'static str, tris: Vec<Tri>) -> BenchScene {
+    let mut mesh = Mesh::cons(tris, Vec3f::cons(0, 0, 0));
+    mesh.generate_normals(CREASE_ANGLE, true);
+    mesh.cull = CullMode::None;
+
+    let mut scene = Scene::new();
+    scene.add(name, mesh);
+    BenchScene { name, scene, camera: Camera::cons(Vec3f::cons(0, 0, 0)), fov: SYNTHETIC_FOV }
+}
+
+/// spread out so the triangles can be told apart
+fn vert(x: Float, y: Float, z: Float, hue: usize) -> Vert {
+    let color = Color::cons((hue * 73 % 256) as Float, (hue * 151 % 256) as Float, (hue * 37 % 256) as Float);
+    Vert::cons(Vec3f::cons(x, y, z), color, Vec2f::cons(0, 0))
+}
+
+/// a square of two triangles at distance `x`, centered on the view axis
+fn square(x: Float, half: Float, hue: usize) -> [Tri; 2] {
+    let (half_height, left, right) = (half * 9. / 16., half, -half);
+    let corners = [
+        vert(x, left, -half_height, hue),
+        vert(x, right, -half_height, hue + 1),
+        vert(x, right, half_height, hue + 2),
+        vert(x, left, half_height, hue + 3),
+    ];
+    [Tri::cons_vert(corners[0], corners[1], corners[2]), Tri::cons_vert(corners[0], corners[2], corners[3])]
+}
+
+/// about eight pixels a triangle at 1280x720
+fn tiny_tris() -> Vec<Tri> {
+    let (width, height) = (2. * DISTANCE, 2. * DISTANCE * 9. / 16.);
+    let (cell_width, cell_height) = (width / TINY_COLUMNS as Float, height / TINY_ROWS as Float);
+    let mut tris = Vec::with_capacity(TINY_COLUMNS * TINY_ROWS * 2);
+    for row in 0..TINY_ROWS {
+        for column in 0..TINY_COLUMNS {
+            let left = DISTANCE - column as Float * cell_width;
+            let bottom = -height / 2. + row as Float * cell_height;
+            let hue = row * TINY_COLUMNS + column;
+            let corners = [
+                vert(DISTANCE, left, bottom, hue),
+                vert(DISTANCE, left - cell_width, bottom, hue + 1),
+                vert(DISTANCE, left - cell_width, bottom + cell_height, hue + 2),
+                vert(DISTANCE, left, bottom + cell_height, hue + 3),
+            ];
+            tris.push(Tri::cons_vert(corners[0], corners[1], corners[2]));
+            tris.push(Tri::cons_vert(corners[0], corners[2], corners[3]));
+        }
+    }
+    tris
+}
+
+/// every triangle gets clipped and together they cover each pixel once
+fn huge_tris() -> Vec<Tri> {
+    let radius = 2. * DISTANCE;
+    let center = vert(DISTANCE, 0., 0., 0);
+    (0..HUGE_SIDES)
+        .map(|side| {
+            let angle = |side: usize| side as Float / HUGE_SIDES as Float * TAU;
+            let (start, end) = (angle(side), angle(side + 1));
+            Tri::cons_vert(
+                center,
+                vert(DISTANCE, radius * start.cos(), radius * start.sin(), side + 1),
+                vert(DISTANCE, radius * end.cos(), radius * end.sin(), side + 2),
+            )
+        })
+        .collect()
+}
+
+/// back to front so each pixel gets shaded `OVERDRAW_LAYERS` times
+fn overdraw_layers() -> Vec<Tri> {
+    (0..OVERDRAW_LAYERS)
+        .flat_map(|layer| {
+            let x = DISTANCE * (1. + (OVERDRAW_LAYERS - layer) as Float * 0.2);
+            square(x, x * 1.05, layer * 4)
+        })
+        .collect()
+}
@@ -0,0 +1,316 @@
+use std::ops::Range;
+
+use crate::bvh::Ray;
+use crate::geometry::Plane;
+use crate::geometry::Tri;
+use crate::math::Mat4;
+use crate::math::Vec3f;
+use crate::Float;
+
+/// bigger meshes are split into chunks of this many triangles with their own bounds
+pub const CHUNK_SIZE: usize = 1024;
+
+/// axis aligned box, an empty one has `min` above `max` and contains nothing
+#[derive(Debug, Clone, Copy)]
+pub struct Aabb {
+    pub min: Vec3f,
+    pub max: Vec3f,
+}
+
+impl Aabb {
+    pub fn cons(min: Vec3f, max: Vec3f) -> Aabb {
+        Aabb { min, max }
+    }
+
+    pub fn empty() -> Aabb {
+        Aabb::cons(Vec3f::cons(Float::MAX, Float::MAX, Float::MAX), Vec3f::cons(Float::MIN, Float::MIN, Float::MIN))
+    }
+
+    pub fn from_tris(tris: &[Tri]) -> Aabb {
+        let mut bounds = Aabb::empty();
+        for tri in tris {
+            bounds.grow(tri.a.pos);
+            bounds.grow(tri.b.pos);
+            bounds.grow(tri.c.pos);
+        }
+        bounds
+    }
+
+    pub fn grow(&mut self, point: Vec3f) {
+        self.min = Vec3f::cons(self.min.x.min(point.x), self.min.y.min(point.y), self.min.z.min(point.z));
+        self.max = Vec3f::cons(self.max.x.max(point.x), self.max.y.max(point.y), self.max.z.max(point.z));
+    }
+
+    pub fn union(&self, other: &Aabb) -> Aabb {
+        let mut bounds = *self;
+        bounds.grow(other.min);
+        bounds.grow(other.max);
+        bounds
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.min.x > self.max.x || self.min.y > self.max.y || self.min.z > self.max.z
+    }
+
+    pub fn center(&self) -> Vec3f {
+        (self.min + self.max) / 2.
+    }
+
+    pub fn size(&self) -> Vec3f {
+        self.max - self.min
+    }
+
+    pub fn corners(&self) -> [Vec3f; 8] {
+        let (min, max) = (self.min, self.max);
+        [
+            Vec3f::cons(min.x, min.y, min.z),
+            Vec3f::cons(max.x, min.y, min.z),
+            Vec3f::cons(min.x, max.y, min.z),
+            Vec3f::cons(max.x, max.y, min.z),
+            Vec3f::cons(min.x, min.y, max.z),
+            Vec3f::cons(max.x, min.y, max.z),
+            Vec3f::cons(min.x, max.y, max.z),
+            Vec3f::cons(max.x, max.y, max.z),
+        ]
+    }
+
+    /// looser than the box around the transformed geometry once rotated
+    pub fn transform(&self, matrix: &Mat4) -> Aabb {
+        let mut bounds = Aabb::empty();
+        if self.is_empty() {
+            return bounds;
+        }
+        self.corners().iter().for_each(|corner| bounds.grow(matrix.transform_point(corner)));
+        bounds
+    }
+
+    /// boxes straddling a corner of the frustum can slip through, which only costs work
+    pub fn intersects_frustum(&self, matrix: &Mat4, frustum: &[Plane]) -> bool {
+        if self.is_empty() {
+            return false;
+        }
+        let corners = self.corners().map(|corner| matrix.transform_point(&corner));
+        frustum.iter().all(|plane| corners.iter().any(|corner| plane.distance(corner) >= 0.))
+    }
+
+    /// zero when the ray starts inside, `inverse` is one over its direction
+    pub fn ray_distance(&self, ray: &Ray, inverse: &Vec3f) -> Option<Float> {
+        let slab = |min: Float, max: Float, origin: Float, inverse: Float| {
+            let (near, far) = ((min - origin) * inverse, (max - origin) * inverse);
+            (near.min(far), near.max(far))
+        };
+        let (x_near, x_far) = slab(self.min.x, self.max.x, ray.origin.x, inverse.x);
+        let (y_near, y_far) = slab(self.min.y, self.max.y, ray.origin.y, inverse.y);
+        let (z_near, z_far) = slab(self.min.z, self.max.z, ray.origin.z, inverse.z);
+        let near = x_near.max(y_near).max(z_near).max(0.);
+        let far = x_far.min(y_far).min(z_far);
+        if near <= far {
+            Some(near)
+        }
+        else {
+            None
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Sphere {
+    pub center: Vec3f,
+    pub radius: Float,
+}
+
+impl Sphere {
+    pub fn cons(center: Vec3f, radius: Float) -> Sphere {
+        Sphere { center, radius }
+    }
+
+    /// the radius reaches the farthest vert rather than the box corners
+    pub fn from_tris(tris: &[Tri], bounds: &Aabb) -> Sphere {
+        let center = bounds.center();
+        let mut farthest: Float = 0.;
+        for tri in tris {
+            for pos in [tri.a.pos, tri.b.pos, tri.c.pos] {
+                farthest = farthest.max((pos - center).inner_prod(&(pos - center)));
+            }
+        }
+        Sphere::cons(center, farthest.sqrt())
+    }
+
+    /// the radius grows by the largest axis scale
+    pub fn transform(&self, matrix: &Mat4) -> Sphere {
+        let linear = matrix.to_mat3();
+        let scale = [Vec3f::cons(1, 0, 0), Vec3f::cons(0, 1, 0), Vec3f::cons(0, 0, 1)]
+            .map(|axis| {
+                let stretched = linear * axis;
+                stretched.inner_prod(&stretched)
+            })
+            .into_iter()
+            .fold(0., Float::max)
+            .sqrt();
+        Sphere::cons(matrix.transform_point(&self.center), self.radius * scale)
+    }
+
+    /// planes face inward like the ones the renderer clips against
+    pub fn intersects_frustum(&self, frustum: &[Plane]) -> bool {
+        frustum.iter().all(|plane| true_distance(plane, &self.center) >= -self.radius)
+    }
+
+    pub fn inside_frustum(&self, frustum: &[Plane]) -> bool {
+        frustum.iter().all(|plane| true_distance(plane, &self.center) >= self.radius)
+    }
+}
+
+/// the planes aren't normalized so the normal's length is divided back out
+#[inline]
+fn true_distance(plane: &Plane, point: &Vec3f) -> Float {
+    plane.distance(point) / plane.normal.inner_prod(&plane.normal).sqrt()
+}
+
+/// a run of a mesh's triangles and what surrounds them, in model space
+#[derive(Debug, Clone)]
+pub struct Chunk {
+    pub tris: Range<usize>,
+    pub bounds: Aabb,
+    pub sphere: Sphere,
+}
+
+impl Chunk {
+    pub fn cons(tris: &[Tri], range: Range<usize>) -> Chunk {
+        let bounds = Aabb::from_tris(&tris[range.clone()]);
+        let sphere = Sphere::from_tris(&tris[range.clone()], &bounds);
+        Chunk { tris: range, bounds, sphere }
+    }
+}
+
+/// how much of the scene the renderer got to skip on the last frame
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CullStats {
+    pub objects: usize,
+    pub objects_culled: usize,
+    pub chunks: usize,
+    pub chunks_culled: usize,
+}
+
+impl CullStats {
+    pub fn add(&mut self, other: &CullStats) {
+        self.objects += other.objects;
+        self.objects_culled += other.objects_culled;
+        self.chunks += other.chunks;
+        self.chunks_culled += other.chunks_culled;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::Mat3;
+    use crate::math::Quat;
+    use crate::PI;
+
+    /// 1 <= x <= 10 and -5 <= y, z <= 5, with some normals left unnormalized
+    fn frustum() -> [Plane; 6] {
+        [
+            Plane::cons(Vec3f::cons(1, 0, 0), -1.),
+            Plane::cons(Vec3f::cons(-1, 0, 0), 10.),
+            Plane::cons(Vec3f::cons(0, 2, 0), 10.),
+            Plane::cons(Vec3f::cons(0, -3, 0), 15.),
+            Plane::cons(Vec3f::cons(0, 0, 1), 5.),
+            Plane::cons(Vec3f::cons(0., 0., -0.5), 2.5),
+        ]
+    }
+
+    fn close(lhs: Vec3f, rhs: Vec3f) -> bool {
+        let gap = lhs - rhs;
+        gap.inner_prod(&gap) < 1e-8
+    }
+
+    fn cube(center: Vec3f, half: Float) -> Aabb {
+        let half = Vec3f::cons(half, half, half);
+        Aabb::cons(center - half, center + half)
+    }
+
+    #[test]
+    fn boxes_against_the_frustum() {
+        let (frustum, identity) = (frustum(), Mat4::identity());
+        assert!(cube(Vec3f::cons(5, 0, 0), 1.).intersects_frustum(&identity, &frustum));
+        assert!(cube(Vec3f::cons(10, 4, 0), 1.5).intersects_frustum(&identity, &frustum));
+        assert!(cube(Vec3f::cons(5., 0., -5.5), 1.).intersects_frustum(&identity, &frustum));
+        assert!(!cube(Vec3f::cons(20, 0, 0), 1.).intersects_frustum(&identity, &frustum));
+        assert!(!cube(Vec3f::cons(5, 8, 0), 1.).intersects_frustum(&identity, &frustum));
+        assert!(!cube(Vec3f::cons(-5, 0, 0), 1.).intersects_frustum(&identity, &frustum));
+        assert!(!Aabb::empty().intersects_frustum(&identity, &frustum));
+
+        // the matrix moves the box before it's tested
+        let moved = Mat4::translation(Vec3f::cons(15, 0, 0));
+        assert!(cube(Vec3f::cons(-10, 0, 0), 1.).intersects_frustum(&moved, &frustum));
+        assert!(!cube(Vec3f::cons(5, 0, 0), 1.).intersects_frustum(&moved, &frustum));
+    }
+
+    #[test]
+    fn spheres_against_the_frustum() {
+        let frustum = frustum();
+        let inside = Sphere::cons(Vec3f::cons(5, 0, 0), 1.);
+        assert!(inside.intersects_frustum(&frustum) && inside.inside_frustum(&frustum));
+
+        for straddling in [Sphere::cons(Vec3f::cons(5., 4.5, 0.), 1.), Sphere::cons(Vec3f::cons(5., 0., -5.5), 1.)] {
+            assert!(straddling.intersects_frustum(&frustum), "{:?}", straddling);
+            assert!(!straddling.inside_frustum(&frustum), "{:?}", straddling);
+        }
+
+        for outside in [Sphere::cons(Vec3f::cons(5, 7, 0), 1.), Sphere::cons(Vec3f::cons(0, 0, 0), 0.5)] {
+            assert!(!outside.intersects_frustum(&frustum), "{:?}", outside);
+            assert!(!outside.inside_frustum(&frustum), "{:?}", outside);
+        }
+    }
+
+    #[test]
+    fn transforms_keep_everything_inside() {
+        let scale = Mat4::from_mat3(Mat3::scaling(Vec3f::cons(1, 3, 2)));
+        let rotation = Mat4::from_mat3(Quat::from_axis_angle(Vec3f::cons(1, 1, 0), PI / 5.).to_mat3());
+        let matrix = Mat4::translation(Vec3f::cons(4, -2, 7)) * rotation * scale;
+
+        let sphere = Sphere::cons(Vec3f::cons(1, 1, 1), 2.).transform(&matrix);
+        assert!((sphere.radius - 6.).abs() < 1e-4, "{}", sphere.radius);
+        assert!(close(sphere.center, matrix.transform_point(&Vec3f::cons(1, 1, 1))));
+
+        let bounds = Aabb::cons(Vec3f::cons(-1, 0, 2), Vec3f::cons(2, 1, 3));
+        let moved = bounds.transform(&matrix);
+        for corner in bounds.corners() {
+            let corner = matrix.transform_point(&corner);
+            let (min, max) = (moved.min - Vec3f::cons(1e-4, 1e-4, 1e-4), moved.max + Vec3f::cons(1e-4, 1e-4, 1e-4));
+            assert!(corner.x >= min.x && corner.y >= min.y && corner.z >= min.z, "{:?}", corner);
+            assert!(corner.x <= max.x && corner.y <= max.y && corner.z <= max.z, "{:?}", corner);
+        }
+
+        let shifted = bounds.transform(&Mat4::translation(Vec3f::cons(1, 2, 3)));
+        assert!(close(shifted.min, Vec3f::cons(0, 2, 5)) && close(shifted.max, Vec3f::cons(3, 3, 6)));
+        assert!(Aabb::empty().transform(&matrix).is_empty());
+    }
+
+    #[test]
+    fn ray_slabs() {
+        let bounds = cube(Vec3f::cons(0, 0, 0), 1.);
+        let distance = |origin: Vec3f, direction: Vec3f| {
+            let inverse = Vec3f::cons(1. / direction.x, 1. / direction.y, 1. / direction.z);
+            bounds.ray_distance(&Ray::cons(origin, direction), &inverse)
+        };
+
+        assert_eq!(distance(Vec3f::cons(-5., 0.5, 0.25), Vec3f::cons(2, 0, 0)), Some(2.));
+        assert_eq!(distance(Vec3f::cons(-3, -3, -3), Vec3f::cons(1, 1, 1)), Some(2.));
+        assert_eq!(distance(Vec3f::cons(0.5, 0., 0.), Vec3f::cons(0, 1, 0)), Some(0.));
+        assert_eq!(distance(Vec3f::cons(-5, 3, 0), Vec3f::cons(1, 0, 0)), None);
+        assert_eq!(distance(Vec3f::cons(5, 0, 0), Vec3f::cons(1, 0, 0)), None);
+        assert_eq!(distance(Vec3f::cons(-5, 0, 0), Vec3f::cons(1, 2, 0)), None);
+    }
+
+    #[test]
+    fn cull_stats_add_up() {
+        let mut total = CullStats { objects: 1, objects_culled: 1, ..Default::default() };
+        total.add(&CullStats { objects: 2, objects_culled: 0, chunks: 5, chunks_culled: 3 });
+        total.add(&CullStats::default());
+        assert_eq!(
+            (total.objects, total.objects_culled, total.chunks, total.chunks_culled),
+            (3, 1, 5, 3)
+        );
+    }
+}
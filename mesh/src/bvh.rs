@@ -0,0 +1,348 @@
+use crate::bounds::Aabb;
+use crate::geometry::Tri;
+use crate::math::Mat4;
+use crate::math::Vec2f;
+use crate::math::Vec3f;
+use crate::Float;
+
+/// leaves stop splitting once they're down to this many triangles
+const LEAF_SIZE: usize = 4;
+
+/// `direction` isn't normalized, distances are in multiples of it
+#[derive(Debug, Clone, Copy)]
+pub struct Ray {
+    pub origin: Vec3f,
+    pub direction: Vec3f,
+}
+
+impl Ray {
+    pub fn cons(origin: Vec3f, direction: Vec3f) -> Ray {
+        Ray { origin, direction }
+    }
+
+    pub fn at(&self, t: Float) -> Vec3f {
+        self.origin + self.direction * t
+    }
+
+    pub fn transform(&self, matrix: &Mat4) -> Ray {
+        Ray::cons(matrix.transform_point(&self.origin), matrix.transform_vector(&self.direction))
+    }
+}
+
+/// `u` and `v` are the barycentric weights of the triangle's `b` and `c` verts
+#[derive(Debug, Clone, Copy)]
+pub struct Hit {
+    pub t: Float,
+    pub tri: usize,
+    pub u: Float,
+    pub v: Float,
+}
+
+impl Hit {
+    pub fn weights(&self) -> Vec3f {
+        Vec3f::cons(1. - self.u - self.v, self.u, self.v)
+    }
+
+    pub fn texpos(&self, tri: &Tri) -> Vec2f {
+        let weights = self.weights();
+        Vec2f::cons(tri.interpolate_tex_u(&weights), tri.interpolate_tex_v(&weights))
+    }
+}
+
+/// an inner node's first child follows it and the second is at `start`
+#[derive(Debug, Clone, Copy)]
+struct BvhNode {
+    bounds: Aabb,
+    start: usize,
+    count: usize,
+}
+
+/// model space, only indices are stored and the triangles are handed in per query
+#[derive(Debug, Clone, Default)]
+pub struct Bvh {
+    nodes: Vec<BvhNode>,
+    order: Vec<usize>,
+}
+
+impl Bvh {
+    /// splits at the median centroid along the longest axis of the centroids
+    pub fn build(tris: &[Tri]) -> Bvh {
+        let mut bvh = Bvh { nodes: Vec::new(), order: (0..tris.len()).collect() };
+        if tris.is_empty() {
+            return bvh;
+        }
+        let centroids: Vec<Vec3f> = tris.iter().map(|tri| (tri.a.pos + tri.b.pos + tri.c.pos) / 3.).collect();
+        bvh.split(tris, &centroids, 0, tris.len());
+        bvh
+    }
+
+    fn split(&mut self, tris: &[Tri], centroids: &[Vec3f], start: usize, count: usize) -> usize {
+        let range = start..start + count;
+        let mut bounds = Aabb::empty();
+        let mut spread = Aabb::empty();
+        for &idx in &self.order[range.clone()] {
+            let tri = &tris[idx];
+            [tri.a.pos, tri.b.pos, tri.c.pos].into_iter().for_each(|pos| bounds.grow(pos));
+            spread.grow(centroids[idx]);
+        }
+
+        let node = self.nodes.len();
+        self.nodes.push(BvhNode { bounds, start, count });
+        if count <= LEAF_SIZE {
+            return node;
+        }
+
+        let size = spread.size();
+        let axis = if size.x >= size.y && size.x >= size.z {
+            0
+        }
+        else if size.y >= size.z {
+            1
+        }
+        else {
+            2
+        };
+        let half = count / 2;
+        self.order[range].select_nth_unstable_by(half, |&lhs, &rhs| {
+            component(centroids[lhs], axis).total_cmp(&component(centroids[rhs], axis))
+        });
+
+        self.split(tris, centroids, start, half);
+        let second = self.split(tris, centroids, start + half, count - half);
+        self.nodes[node] = BvhNode { bounds, start: second, count: 0 };
+        node
+    }
+
+    pub fn closest(&self, tris: &[Tri], ray: &Ray) -> Option<Hit> {
+        let mut closest: Option<Hit> = None;
+        self.traverse(ray, Float::MAX, |leaf, limit| {
+            for &idx in leaf {
+                if let Some(hit) = intersect(&tris[idx], idx, ray)
+                    && hit.t < *limit
+                {
+                    *limit = hit.t;
+                    closest = Some(hit);
+                }
+            }
+            false
+        });
+        closest
+    }
+
+    /// whether anything is hit closer than `max_t`, stops at the first one
+    pub fn any(&self, tris: &[Tri], ray: &Ray, max_t: Float) -> bool {
+        let mut found = false;
+        self.traverse(ray, max_t, |leaf, _| {
+            found = leaf.iter().any(|&idx| intersect(&tris[idx], idx, ray).is_some_and(|hit| hit.t < max_t));
+            found
+        });
+        found
+    }
+
+    /// every hit in front of the ray's origin, nearest first
+    pub fn all(&self, tris: &[Tri], ray: &Ray) -> Vec<Hit> {
+        let mut hits = Vec::new();
+        self.traverse(ray, Float::MAX, |leaf, _| {
+            hits.extend(leaf.iter().filter_map(|&idx| intersect(&tris[idx], idx, ray)));
+            false
+        });
+        hits.sort_by(|lhs, rhs| lhs.t.total_cmp(&rhs.t));
+        hits
+    }
+
+    /// nearer child first, `visit` can shrink the limit and returns true to stop
+    fn traverse(&self, ray: &Ray, mut limit: Float, mut visit: impl FnMut(&[usize], &mut Float) -> bool) {
+        if self.nodes.is_empty() {
+            return;
+        }
+        let inverse = Vec3f::cons(1. / ray.direction.x, 1. / ray.direction.y, 1. / ray.direction.z);
+        let mut stack = vec![0];
+        while let Some(idx) = stack.pop() {
+            let node = self.nodes[idx];
+            if node.bounds.ray_distance(ray, &inverse).is_none_or(|near| near > limit) {
+                continue;
+            }
+            if node.count > 0 {
+                if visit(&self.order[node.start..node.start + node.count], &mut limit) {
+                    return;
+                }
+                continue;
+            }
+
+            let (first, second) = (idx + 1, node.start);
+            let near = |child: usize| self.nodes[child].bounds.ray_distance(ray, &inverse).unwrap_or(Float::MAX);
+            if near(first) <= near(second) {
+                stack.extend([second, first]);
+            }
+            else {
+                stack.extend([first, second]);
+            }
+        }
+    }
+}
+
+#[inline]
+fn component(vec: Vec3f, axis: usize) -> Float {
+    match axis {
+        0 => vec.x,
+        1 => vec.y,
+        _ => vec.z,
+    }
+}
+
+/// moller trumbore, both faces count since picking shouldn't care about culling
+fn intersect(tri: &Tri, idx: usize, ray: &Ray) -> Option<Hit> {
+    let ab = tri.b.pos - tri.a.pos;
+    let ac = tri.c.pos - tri.a.pos;
+    let cross = ray.direction.cross(&ac);
+    let det = ab.inner_prod(&cross);
+    if det == 0. {
+        return None;
+    }
+
+    let inv_det = 1. / det;
+    let offset = ray.origin - tri.a.pos;
+    let u = offset.inner_prod(&cross) * inv_det;
+    if !(0. ..=1.).contains(&u) {
+        return None;
+    }
+    let across = offset.cross(&ab);
+    let v = ray.direction.inner_prod(&across) * inv_det;
+    if v < 0. || u + v > 1. {
+        return None;
+    }
+
+    let t = ac.inner_prod(&across) * inv_det;
+    if t > 0. {
+        Some(Hit { t, tri: idx, u, v })
+    }
+    else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Lcg;
+
+    /// a few big triangles mixed in so leaf bounds overlap
+    fn soup(rng: &mut Lcg, count: usize) -> Vec<Tri> {
+        (0..count)
+            .map(|idx| {
+                let size = match idx % 10 {
+                    0 => 8.,
+                    _ => 1.5,
+                };
+                let center = rng.vec(-10., 10.);
+                let mut corner = || center + rng.vec(-size, size);
+                Tri::cons_pos(corner(), corner(), corner())
+            })
+            .collect()
+    }
+
+    /// the axis aligned rays leave `inverse` with infinite components
+    fn rays(rng: &mut Lcg, count: usize) -> Vec<Ray> {
+        let mut rays: Vec<Ray> = (0..count)
+            .map(|_| {
+                let origin = rng.vec(-15., 15.);
+                Ray::cons(origin, rng.vec(-10., 10.) - origin)
+            })
+            .collect();
+        let axes = [
+            Vec3f::cons(1, 0, 0),
+            Vec3f::cons(-1, 0, 0),
+            Vec3f::cons(0, 1, 0),
+            Vec3f::cons(0, -1, 0),
+            Vec3f::cons(0, 0, 1),
+            Vec3f::cons(0, 0, -1),
+        ];
+        for _ in 0..count / 4 {
+            for axis in axes {
+                let origin = rng.vec(-10., 10.) - axis * 20.;
+                rays.push(Ray::cons(origin, axis));
+            }
+        }
+        rays
+    }
+
+    fn brute_force(tris: &[Tri], ray: &Ray) -> Vec<Hit> {
+        let mut hits: Vec<Hit> = tris.iter().enumerate().filter_map(|(idx, tri)| intersect(tri, idx, ray)).collect();
+        hits.sort_by(|lhs, rhs| lhs.t.total_cmp(&rhs.t));
+        hits
+    }
+
+    #[test]
+    fn queries_match_brute_force() {
+        let mut rng = Lcg(7);
+        let tris = soup(&mut rng, 400);
+        let bvh = Bvh::build(&tris);
+
+        let mut hit_rays = 0;
+        for ray in rays(&mut rng, 200) {
+            let expected = brute_force(&tris, &ray);
+            hit_rays += usize::from(!expected.is_empty());
+
+            let closest = bvh.closest(&tris, &ray);
+            assert_eq!(closest.map(|hit| hit.tri), expected.first().map(|hit| hit.tri), "closest along {:?}", ray);
+            if let (Some(closest), Some(first)) = (closest, expected.first()) {
+                assert_eq!(closest.t, first.t);
+                assert_eq!((closest.u, closest.v), (first.u, first.v));
+            }
+
+            let mut all: Vec<usize> = bvh.all(&tris, &ray).iter().map(|hit| hit.tri).collect();
+            let mut every: Vec<usize> = expected.iter().map(|hit| hit.tri).collect();
+            all.sort();
+            every.sort();
+            assert_eq!(all, every, "all along {:?}", ray);
+
+            for max_t in [0.25, 0.5, 1., Float::MAX] {
+                let any = expected.iter().any(|hit| hit.t < max_t);
+                assert_eq!(bvh.any(&tris, &ray, max_t), any, "any before {} along {:?}", max_t, ray);
+            }
+        }
+        assert!(hit_rays > 50, "only {} rays hit anything", hit_rays);
+    }
+
+    #[test]
+    fn all_comes_back_nearest_first() {
+        let mut rng = Lcg(11);
+        let tris = soup(&mut rng, 200);
+        let bvh = Bvh::build(&tris);
+        for ray in rays(&mut rng, 50) {
+            let hits = bvh.all(&tris, &ray);
+            assert!(hits.windows(2).all(|pair| pair[0].t <= pair[1].t));
+        }
+    }
+
+    #[test]
+    fn intersect_reports_weights_and_both_faces() {
+        let tri = Tri::cons_pos(Vec3f::cons(5, 0, 0), Vec3f::cons(5, 4, 0), Vec3f::cons(5, 0, 4));
+        let front = Ray::cons(Vec3f::cons(0, 1, 2), Vec3f::cons(1, 0, 0));
+        let hit = intersect(&tri, 3, &front).unwrap();
+        assert_eq!(hit.tri, 3);
+        assert!((hit.t - 5.).abs() < 1e-5);
+        assert!((hit.u - 0.25).abs() < 1e-5 && (hit.v - 0.5).abs() < 1e-5);
+        let miss = front.at(hit.t) - Vec3f::cons(5, 1, 2);
+        assert!(miss.inner_prod(&miss) < 1e-10);
+
+        let back = Ray::cons(Vec3f::cons(10, 1, 2), Vec3f::cons(-1, 0, 0));
+        assert!(intersect(&tri, 3, &back).is_some());
+
+        let behind = Ray::cons(Vec3f::cons(10, 1, 2), Vec3f::cons(1, 0, 0));
+        assert!(intersect(&tri, 3, &behind).is_none());
+        let outside = Ray::cons(Vec3f::cons(0, 3, 3), Vec3f::cons(1, 0, 0));
+        assert!(intersect(&tri, 3, &outside).is_none());
+        let parallel = Ray::cons(Vec3f::cons(5, -1, 1), Vec3f::cons(0, 1, 0));
+        assert!(intersect(&tri, 3, &parallel).is_none());
+    }
+
+    #[test]
+    fn empty_bvh_hits_nothing() {
+        let bvh = Bvh::build(&[]);
+        let ray = Ray::cons(Vec3f::cons(0, 0, 0), Vec3f::cons(1, 0, 0));
+        assert!(bvh.closest(&[], &ray).is_none());
+        assert!(!bvh.any(&[], &ray, Float::MAX));
+        assert!(bvh.all(&[], &ray).is_empty());
+    }
+}
@@ -0,0 +1,430 @@
+use image::ImageFormat;
+
+use crate::geometry::CullMode;
+use crate::math::Vec3f;
+use crate::render_utils::Antialias;
+use crate::render_utils::Projection;
+use crate::render_utils::ToneMap;
+use crate::renderer::Normals;
+use crate::renderer::RasterMode;
+use crate::renderer::Shading;
+use crate::renderer::ViewMode;
+use crate::texture::Filter;
+use crate::Float;
+use crate::BACKGROUND;
+
+pub const USAGE: &str = "usage: mesh [--model <file.obj>] [--texture <image>] [--scale <factor>]
+            [--size <width>x<height>] [--resmod <divisor>] [--fps <rate>]
+            [--fov <degrees>] [--background <rrggbb>]
+            [--camera <x,y,z>] [--target <x,y,z>]
+            [--projection <perspective|orthographic|isometric>]
+            [--headless <output.png|output.ppm>] [--bench <frames>]
+            [--threads <count>]
+            [--raster <bresenham|edge>] [--filter <nearest|bilinear|trilinear>]
+            [--shading <vertex|pixel>] [--normals <face|vertex>]
+            [--shadows <on|off>] [--aa <off|ssaa<factor>|msaa<2|4|8>>]
+            [--tonemap <clamp|reinhard|aces>]
+            [--view <shaded|depth|normals|uvs|ids|overdraw>]
+            [--cull <none|back|front>] [--flip-winding]";
+
+const DEFAULT_MODEL: &str = "portal/portal.obj";
+const DEFAULT_TEXTURE: &str = "portal/portal_tex.jpg";
+const DEFAULT_SCALE: Float = 55.;
+
+const PROJECTIONS: &str = "'perspective', 'orthographic' or 'isometric'";
+const VIEW_MODES: &str = "'shaded', 'depth', 'normals', 'uvs', 'ids' or 'overdraw'";
+
+/// what the command line asks for, `--help` only wants the usage printed
+pub enum Command {
+    Run(Config),
+    Help,
+}
+
+/// `scale` is none when the model should be auto-framed
+pub struct Config {
+    pub model: String,
+    pub texture: Option<String>,
+    pub scale: Option<Float>,
+    pub width: usize,
+    pub height: usize,
+    pub fps: usize,
+    pub fov: Float,
+    pub background: u32,
+    pub camera: Option<Vec3f>,
+    pub target: Option<Vec3f>,
+    pub projection: Projection,
+    pub headless: Option<String>,
+    /// frames timed per scene and resolution in the benchmark mode
+    pub bench: Option<usize>,
+    pub threads: usize,
+    pub raster: RasterMode,
+    pub filter: Filter,
+    pub shading: Shading,
+    pub normals: Normals,
+    pub shadows: bool,
+    pub antialias: Antialias,
+    pub tone_map: ToneMap,
+    pub view: ViewMode,
+    pub cull: CullMode,
+    pub flip_winding: bool,
+}
+
+impl Config {
+    /// the first item is the program name, like `std::env::args`
+    pub fn build_from_args(args: impl IntoIterator<Item = String>) -> Result<Command, String> {
+        let mut config = Config {
+            model: DEFAULT_MODEL.to_string(),
+            texture: None,
+            scale: None,
+            width: 2560,
+            height: 1200,
+            fps: 120,
+            fov: 90.,
+            background: BACKGROUND,
+            camera: None,
+            target: None,
+            projection: Projection::Perspective,
+            headless: None,
+            bench: None,
+            threads: 1,
+            raster: RasterMode::Bresenham,
+            filter: Filter::Trilinear,
+            shading: Shading::PerVertex,
+            normals: Normals::Vertex,
+            shadows: true,
+            antialias: Antialias::Off,
+            tone_map: ToneMap::Clamp,
+            view: ViewMode::Shaded,
+            cull: CullMode::Back,
+            flip_winding: false,
+        };
+
+        let mut model = None;
+        let mut resmod = 1;
+        let mut args = args.into_iter().skip(1);
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--model" => {
+                    model = Some(args.next().ok_or("--model expects an obj file")?);
+                }
+                "--texture" => {
+                    config.texture = Some(args.next().ok_or("--texture expects an image file")?);
+                }
+                "--scale" => {
+                    let scale = args.next().ok_or("--scale expects a factor")?;
+                    let scale = scale.parse().ok().filter(|scale: &Float| *scale > 0.);
+                    config.scale = Some(scale.ok_or("--scale expects a positive number".to_string())?);
+                }
+                "--size" => {
+                    let size = args.next().ok_or("--size expects <width>x<height>")?;
+                    (config.width, config.height) = parse_size(&size).ok_or(format!("invalid window size '{}'", size))?;
+                }
+                "--resmod" => {
+                    let divisor = args.next().ok_or("--resmod expects a divisor")?;
+                    resmod = divisor.parse().map_err(|_| format!("invalid resolution divisor '{}'", divisor))?;
+                    resmod = resmod.max(1);
+                }
+                "--fps" => {
+                    let fps = args.next().ok_or("--fps expects a frame rate")?;
+                    config.fps = fps.parse().map_err(|_| format!("invalid frame rate '{}'", fps))?;
+                }
+                "--fov" => {
+                    let fov = args.next().ok_or("--fov expects an angle in degrees")?;
+                    let fov = fov.parse().ok().filter(|fov: &Float| *fov > 0. && *fov < 180.);
+                    config.fov = fov.ok_or("--fov expects an angle between 0 and 180 degrees".to_string())?;
+                }
+                "--background" => {
+                    let color = args.next().ok_or("--background expects a color like 'bbbbbb'")?;
+                    config.background = parse_color(&color).ok_or(format!("invalid background color '{}'", color))?;
+                }
+                "--camera" => {
+                    let position = args.next().ok_or("--camera expects a position like '-100,0,0'")?;
+                    let camera = parse_vec3(&position).ok_or(format!("invalid camera position '{}'", position))?;
+                    config.camera = Some(camera);
+                }
+                "--target" => {
+                    let target = args.next().ok_or("--target expects a point like '0,0,0'")?;
+                    let target = parse_vec3(&target).ok_or(format!("invalid camera target '{}'", target))?;
+                    config.target = Some(target);
+                }
+                "--projection" => {
+                    config.projection = match args.next().as_deref() {
+                        Some("perspective") => Projection::Perspective,
+                        Some("orthographic") => Projection::Orthographic,
+                        Some("isometric") => Projection::Isometric,
+                        Some(other) => return Err(format!("unknown projection '{}'", other)),
+                        None => return Err(format!("--projection expects one of {}", PROJECTIONS)),
+                    };
+                }
+                "--headless" => {
+                    let path = args.next().ok_or("--headless expects an output path")?;
+                    if !ImageFormat::from_path(&path).is_ok_and(|format| format.writing_enabled()) {
+                        return Err(format!("can't tell what image format to write '{}' as", path));
+                    }
+                    config.headless = Some(path);
+                }
+                "--bench" => {
+                    let frames = args.next().ok_or("--bench expects a frame count")?;
+                    let frames = frames.parse().ok().filter(|frames: &usize| *frames > 0);
+                    config.bench = Some(frames.ok_or("--bench expects a positive frame count".to_string())?);
+                }
+                "--threads" => {
+                    let count = args.next().ok_or("--threads expects a thread count")?;
+                    config.threads = count.parse().map_err(|_| format!("invalid thread count '{}'", count))?;
+                    config.threads = config.threads.max(1);
+                }
+                "--raster" => {
+                    config.raster = match args.next().as_deref() {
+                        Some("bresenham") => RasterMode::Bresenham,
+                        Some("edge") => RasterMode::EdgeFunction,
+                        Some(other) => return Err(format!("unknown rasterizer '{}'", other)),
+                        None => return Err("--raster expects 'bresenham' or 'edge'".to_string()),
+                    };
+                }
+                "--filter" => {
+                    config.filter = match args.next().as_deref() {
+                        Some("nearest") => Filter::Nearest,
+                        Some("bilinear") => Filter::Bilinear,
+                        Some("trilinear") => Filter::Trilinear,
+                        Some(other) => return Err(format!("unknown texture filter '{}'", other)),
+                        None => return Err("--filter expects 'nearest', 'bilinear' or 'trilinear'".to_string()),
+                    };
+                }
+                "--shading" => {
+                    config.shading = match args.next().as_deref() {
+                        Some("vertex") => Shading::PerVertex,
+                        Some("pixel") => Shading::PerPixel,
+                        Some(other) => return Err(format!("unknown shading rate '{}'", other)),
+                        None => return Err("--shading expects 'vertex' or 'pixel'".to_string()),
+                    };
+                }
+                "--normals" => {
+                    config.normals = match args.next().as_deref() {
+                        Some("face") => Normals::Face,
+                        Some("vertex") => Normals::Vertex,
+                        Some(other) => return Err(format!("unknown normal source '{}'", other)),
+                        None => return Err("--normals expects 'face' or 'vertex'".to_string()),
+                    };
+                }
+                "--shadows" => {
+                    config.shadows = match args.next().as_deref() {
+                        Some("on") => true,
+                        Some("off") => false,
+                        Some(other) => return Err(format!("expected 'on' or 'off' for shadows, got '{}'", other)),
+                        None => return Err("--shadows expects 'on' or 'off'".to_string()),
+                    };
+                }
+                "--aa" => {
+                    let mode = args.next().ok_or("--aa expects 'off', 'ssaa<factor>' or 'msaa<samples>'")?;
+                    config.antialias = parse_antialias(&mode).ok_or(format!("unknown anti-aliasing mode '{}'", mode))?;
+                }
+                "--tonemap" => {
+                    config.tone_map = match args.next().as_deref() {
+                        Some("clamp") => ToneMap::Clamp,
+                        Some("reinhard") => ToneMap::Reinhard,
+                        Some("aces") => ToneMap::Aces,
+                        Some(other) => return Err(format!("unknown tone mapping '{}'", other)),
+                        None => return Err("--tonemap expects 'clamp', 'reinhard' or 'aces'".to_string()),
+                    };
+                }
+                "--view" => {
+                    config.view = match args.next().as_deref() {
+                        Some("shaded") => ViewMode::Shaded,
+                        Some("depth") => ViewMode::Depth,
+                        Some("normals") => ViewMode::Normals,
+                        Some("uvs") => ViewMode::Uvs,
+                        Some("ids") => ViewMode::TriangleIds,
+                        Some("overdraw") => ViewMode::Overdraw,
+                        Some(other) => return Err(format!("unknown view mode '{}'", other)),
+                        None => return Err(format!("--view expects one of {}", VIEW_MODES)),
+                    };
+                }
+                "--cull" => {
+                    config.cull = match args.next().as_deref() {
+                        Some("none") => CullMode::None,
+                        Some("back") => CullMode::Back,
+                        Some("front") => CullMode::Front,
+                        Some(other) => return Err(format!("unknown cull mode '{}'", other)),
+                        None => return Err("--cull expects 'none', 'back' or 'front'".to_string()),
+                    };
+                }
+                "--flip-winding" => {
+                    config.flip_winding = true;
+                }
+                "--help" | "-h" => {
+                    return Ok(Command::Help);
+                }
+                other => {
+                    return Err(format!("unknown argument '{}'\n{}", other, USAGE));
+                }
+            }
+        }
+
+        match model {
+            Some(model) => config.model = model,
+            None => {
+                config.texture = config.texture.or(Some(DEFAULT_TEXTURE.to_string()));
+                config.scale = config.scale.or(Some(DEFAULT_SCALE));
+            }
+        }
+        config.width = (config.width / resmod).max(1);
+        config.height = (config.height / resmod).max(1);
+
+        Ok(Command::Run(config))
+    }
+}
+
+/// `1280x720`
+fn parse_size(size: &str) -> Option<(usize, usize)> {
+    let (width, height) = size.split_once('x')?;
+    let (width, height) = (width.parse().ok()?, height.parse().ok()?);
+    if width > 0 && height > 0 {
+        Some((width, height))
+    }
+    else {
+        None
+    }
+}
+
+/// six hex digits with an optional `#` or `0x` in front, always opaque
+fn parse_color(color: &str) -> Option<u32> {
+    let digits = color.strip_prefix('#').or(color.strip_prefix("0x")).unwrap_or(color);
+    match digits.len() {
+        6 => u32::from_str_radix(digits, 16).ok().map(|rgb| rgb | 0xff000000),
+        _ => None,
+    }
+}
+
+/// `x,y,z` with no spaces
+fn parse_vec3(vec: &str) -> Option<Vec3f> {
+    let parts: Vec<Float> = vec.split(',').map(|part| part.parse().ok()).collect::<Option<_>>()?;
+    match parts[..] {
+        [x, y, z] => Some(Vec3f::cons(x, y, z)),
+        _ => None,
+    }
+}
+
+/// `ssaa2` is 2x2 samples per pixel, `msaa4` is four
+fn parse_antialias(mode: &str) -> Option<Antialias> {
+    if mode == "off" {
+        return Some(Antialias::Off);
+    }
+    if let Some(factor) = mode.strip_prefix("ssaa") {
+        return factor.parse().ok().filter(|factor| *factor >= 1).map(Antialias::Supersample);
+    }
+    match mode.strip_prefix("msaa")?.parse().ok()? {
+        count @ (2 | 4 | 8) => Some(Antialias::Multisample(count)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Command, String> {
+        Config::build_from_args(["mesh"].iter().chain(args).map(|arg| arg.to_string()))
+    }
+
+    fn run(args: &[&str]) -> Config {
+        match parse(args) {
+            Ok(Command::Run(config)) => config,
+            Ok(Command::Help) => panic!("{:?} asked for help", args),
+            Err(message) => panic!("{:?} failed: {}", args, message),
+        }
+    }
+
+    fn error(args: &[&str]) -> String {
+        match parse(args) {
+            Err(message) => message,
+            Ok(_) => panic!("{:?} parsed", args),
+        }
+    }
+
+    #[test]
+    fn sizes() {
+        assert_eq!(parse_size("1280x720"), Some((1280, 720)));
+        assert_eq!(parse_size("1x1"), Some((1, 1)));
+        for size in ["1280", "0x720", "1280x0", "x720", "1280x", "-1x5", "12.5x7", "1280X720", ""] {
+            assert_eq!(parse_size(size), None, "{:?}", size);
+        }
+    }
+
+    #[test]
+    fn colors() {
+        assert_eq!(parse_color("bbbbbb"), Some(0xffbbbbbb));
+        assert_eq!(parse_color("#102030"), Some(0xff102030));
+        assert_eq!(parse_color("0xA0b0C0"), Some(0xffa0b0c0));
+        for color in ["bbb", "bbbbbbbb", "gggggg", "#0x102030", "", "#"] {
+            assert_eq!(parse_color(color), None, "{:?}", color);
+        }
+    }
+
+    #[test]
+    fn vectors() {
+        let vec = parse_vec3("-100,0.5,3").unwrap();
+        assert_eq!((vec.x, vec.y, vec.z), (-100., 0.5, 3.));
+        for vec in ["1,2", "1,2,3,4", "1, 2, 3", "a,b,c", "", "1,,3"] {
+            assert!(parse_vec3(vec).is_none(), "{:?}", vec);
+        }
+    }
+
+    #[test]
+    fn antialias_modes() {
+        assert_eq!(parse_antialias("off"), Some(Antialias::Off));
+        assert_eq!(parse_antialias("ssaa1"), Some(Antialias::Supersample(1)));
+        assert_eq!(parse_antialias("ssaa3"), Some(Antialias::Supersample(3)));
+        for count in [2, 4, 8] {
+            assert_eq!(parse_antialias(&format!("msaa{}", count)), Some(Antialias::Multisample(count)));
+        }
+        for mode in ["ssaa0", "ssaa", "msaa3", "msaa16", "msaa", "on", "fxaa"] {
+            assert_eq!(parse_antialias(mode), None, "{:?}", mode);
+        }
+    }
+
+    #[test]
+    fn resmod_divides_the_window_size() {
+        let config = run(&["--size", "1280x720", "--resmod", "4"]);
+        assert_eq!((config.width, config.height), (320, 180));
+        // order doesn't matter and nothing goes below one pixel
+        let config = run(&["--resmod", "1000", "--size", "640x480"]);
+        assert_eq!((config.width, config.height), (1, 1));
+        let config = run(&["--resmod", "0", "--size", "640x480"]);
+        assert_eq!((config.width, config.height), (640, 480));
+        assert!(error(&["--resmod", "half"]).contains("'half'"));
+    }
+
+    #[test]
+    fn unknown_and_incomplete_arguments_are_errors() {
+        let message = error(&["--model", "a.obj", "--wireframe"]);
+        assert!(message.starts_with("unknown argument '--wireframe'") && message.contains(USAGE), "{}", message);
+        assert!(error(&["--model"]).contains("--model expects"));
+        assert!(error(&["--view", "sideways"]).contains("'sideways'"));
+        assert!(error(&["--scale", "-2"]).contains("positive"));
+        assert!(matches!(parse(&["--size", "10x10", "--help"]), Ok(Command::Help)));
+    }
+
+    #[test]
+    fn default_model_brings_its_texture_and_scale() {
+        let config = run(&[]);
+        assert_eq!(config.model, DEFAULT_MODEL);
+        assert_eq!(config.texture.as_deref(), Some(DEFAULT_TEXTURE));
+        assert_eq!(config.scale, Some(DEFAULT_SCALE));
+
+        let config = run(&["--scale", "2"]);
+        assert_eq!((config.texture.as_deref(), config.scale), (Some(DEFAULT_TEXTURE), Some(2.)));
+
+        let config = run(&["--model", "cube.obj"]);
+        assert_eq!((config.model.as_str(), config.texture, config.scale), ("cube.obj", None, None));
+    }
+
+    #[test]
+    fn headless_output_needs_a_known_extension() {
+        for path in ["frame.png", "out/frame.ppm", "frame.JPG"] {
+            assert_eq!(run(&["--headless", path]).headless.as_deref(), Some(path));
+        }
+        for path in ["frame", "frame.txt", "frame."] {
+            assert!(error(&["--headless", path]).contains(path), "{:?}", path);
+        }
+    }
+}
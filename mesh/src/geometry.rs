@@ -1,37 +1,101 @@
 #![allow(dead_code)]
 
-use std::fs::read_to_string;
+use std::collections::HashMap;
 use std::mem::swap;
-
+use std::mem::take;
+use std::ops::Range;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::bounds::Aabb;
+use crate::bounds::Chunk;
+use crate::bounds::Sphere;
+use crate::bounds::CHUNK_SIZE;
+use crate::bvh::Bvh;
+use crate::bvh::Hit;
+use crate::bvh::Ray;
+use crate::lighting::LightSample;
+use crate::material::Material;
+use crate::math::Mat4;
+use crate::math::Transform;
 use crate::math::Vec2f;
 use crate::math::Vec3f;
+use crate::obj::ObjData;
+use crate::obj::ObjError;
+use crate::obj::ObjWarning;
 use crate::render_utils::Color;
 use crate::texture::Texture;
 use crate::Float;
 use crate::Int;
+use crate::PI;
+
+/// how far apart two faces can bend before generated normals keep the edge sharp
+pub const CREASE_ANGLE: Float = PI / 3.;
 
+/// `world_normal` is the unit face normal in world space
 pub struct PolyData {
     pub tri: Tri,
-    pub normal: Vec3f,
-    pub lighting: Float,
+    pub world_normal: Vec3f,
+    /// lights with a shadow map go in `shadowed` so they can be shadowed per pixel
+    pub shading: [LightSample; 3],
+    pub shadowed: [LightSample; 3],
+    /// index of the source triangle in its mesh, shared by every clipped piece
+    pub id: usize,
 }
 
 impl PolyData {
-    pub fn cons(tri: Tri, normal: Vec3f, lighting: Float) -> PolyData {
-        PolyData { tri, normal, lighting }
+    pub fn cons(
+        tri: Tri, world_normal: Vec3f, shading: [LightSample; 3], shadowed: [LightSample; 3], id: usize,
+    ) -> PolyData {
+        PolyData { tri, world_normal, shading, shadowed, id }
     }
 }
 
 #[derive(Clone, Copy)]
+/// `world` survives the view transform and clipping, a zero `normal` means there isn't one
 pub struct Vert {
     pub pos: Vec3f,
+    pub world: Vec3f,
+    pub normal: Vec3f,
     pub texpos: Vec2f,
     pub color: Color,
 }
 
 impl Vert {
     pub fn cons(pos: Vec3f, color: Color, texpos: Vec2f) -> Vert {
-        Vert { pos, color, texpos }
+        Vert { pos, world: pos, normal: Vec3f::cons(0, 0, 0), color, texpos }
+    }
+
+    pub fn lerp(&self, other: &Vert, t: Float) -> Vert {
+        Vert {
+            pos: self.pos.lerp(&other.pos, t),
+            world: self.world.lerp(&other.world, t),
+            normal: self.normal.lerp(&other.normal, t),
+            texpos: self.texpos.lerp(&other.texpos, t),
+            color: self.color.lerp(&other.color, t),
+        }
+    }
+
+    pub fn has_normal(&self) -> bool {
+        self.normal.inner_prod(&self.normal) > 0.
+    }
+}
+
+/// half-space n . p + d >= 0, the normal doesn't need to be unit length
+#[derive(Clone, Copy)]
+pub struct Plane {
+    pub normal: Vec3f,
+    pub offset: Float,
+}
+
+impl Plane {
+    pub fn cons(normal: Vec3f, offset: Float) -> Plane {
+        Plane { normal, offset }
+    }
+
+    #[inline]
+    pub fn distance(&self, point: &Vec3f) -> Float {
+        self.normal.inner_prod(point) + self.offset
     }
 }
 
@@ -40,6 +104,9 @@ pub struct Tri {
     pub a: Vert,
     pub b: Vert,
     pub c: Vert,
+    pub material: usize,
+    /// the obj smoothing group, 0 is `s off`. none when the source never said
+    pub smoothing: Option<u32>,
 }
 
 impl Tri {
@@ -48,11 +115,13 @@ impl Tri {
             a: Vert::cons(a, Color::cons(255, 0, 0), Vec2f::cons(0, 0)),
             b: Vert::cons(b, Color::cons(0, 255, 0), Vec2f::cons(0, 0)),
             c: Vert::cons(c, Color::cons(0, 0, 255), Vec2f::cons(0, 0)),
+            material: 0,
+            smoothing: None,
         }
     }
 
     pub fn cons_vert(a: Vert, b: Vert, c: Vert) -> Tri {
-        Tri { a, b, c }
+        Tri { a, b, c, material: 0, smoothing: None }
     }
 
     pub fn sort_verts_vertical(&mut self) {
@@ -82,6 +151,13 @@ impl Tri {
         Vec3f::cons(self.a.color.blue, self.b.color.blue, self.c.color.blue)
     }
 
+    /// twice the signed area, positive when counter clockwise on screen
+    #[inline]
+    pub fn screen_area(&self) -> Float {
+        let (ab, ac) = (self.b.pos - self.a.pos, self.c.pos - self.a.pos);
+        ab.x * ac.y - ab.y * ac.x
+    }
+
     pub fn get_normal(&self) -> Vec3f {
         (self.a.pos - self.b.pos).cross(&(self.a.pos - self.c.pos)).get_normalized()
     }
@@ -140,6 +216,12 @@ impl Tri {
         self.c.pos += vec;
     }
 
+    pub fn transform(&mut self, matrix: &Mat4) {
+        self.a.pos = matrix.transform_point(&self.a.pos);
+        self.b.pos = matrix.transform_point(&self.b.pos);
+        self.c.pos = matrix.transform_point(&self.c.pos);
+    }
+
     pub fn lumped_left(&self) -> bool {
         let v1 = self.a.pos - self.b.pos;
         let v2 = self.a.pos - self.c.pos;
@@ -152,125 +234,310 @@ impl Tri {
         v1.x * v2.y - v1.y * v2.x >= 0.
     }
 
-    pub fn behind_view(&self) -> bool {
-        self.a.pos.z < 0.1 || self.b.pos.z < 0.1 || self.c.pos.z < 0.1
+    pub fn inside(&self, planes: &[Plane]) -> bool {
+        planes.iter().all(|plane| {
+            plane.distance(&self.a.pos) >= 0.
+                && plane.distance(&self.b.pos) >= 0.
+                && plane.distance(&self.c.pos) >= 0.
+        })
+    }
+
+    /// sutherland-hodgman against every plane, then fanned back into triangles
+    pub fn clip(&self, planes: &[Plane]) -> Vec<Tri> {
+        if self.inside(planes) {
+            return vec![*self];
+        }
+
+        let mut polygon = vec![self.a, self.b, self.c];
+        let mut clipped = Vec::with_capacity(8);
+        for plane in planes {
+            clipped.clear();
+            for (idx, current) in polygon.iter().enumerate() {
+                let next = &polygon[(idx + 1) % polygon.len()];
+                let current_dist = plane.distance(&current.pos);
+                let next_dist = plane.distance(&next.pos);
+
+                if current_dist >= 0. {
+                    clipped.push(*current);
+                }
+                if (current_dist >= 0.) != (next_dist >= 0.) {
+                    let t = current_dist / (current_dist - next_dist);
+                    clipped.push(current.lerp(next, t));
+                }
+            }
+            swap(&mut polygon, &mut clipped);
+            if polygon.len() < 3 {
+                return Vec::new();
+            }
+        }
+
+        (2..polygon.len())
+            .map(|idx| Tri { a: polygon[0], b: polygon[idx - 1], c: polygon[idx], ..*self })
+            .collect()
+    }
+}
+
+pub struct MeshGroup {
+    pub object: String,
+    pub name: String,
+    pub tris: Range<usize>,
+}
+
+/// front faces wind counter clockwise on screen
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CullMode {
+    None,
+    Back,
+    Front,
+}
+
+impl CullMode {
+    /// degenerate triangles are left for the rasterizer to drop
+    #[inline]
+    pub fn culls(self, area: Float) -> bool {
+        match self {
+            CullMode::None => false,
+            CullMode::Back => area < 0.,
+            CullMode::Front => area > 0.,
+        }
     }
 }
 
+/// `bounds`, `sphere`, `chunks` and `bvh` are in model space and need `update_bounds` after edits
 pub struct Mesh {
     pub tris: Vec<Tri>,
-    pub center: Vec3f,
-    pub rotation: Vec3f,
-    pub texture: Option<Texture>,
+    pub groups: Vec<MeshGroup>,
+    pub transform: Transform,
+    pub materials: Vec<Material>,
+    pub cull: CullMode,
+    pub bounds: Aabb,
+    pub sphere: Sphere,
+    pub chunks: Vec<Chunk>,
+    pub bvh: Bvh,
 }
 
 impl Mesh {
-    pub fn cons(tris: Vec<Tri>, center: Vec3f, texpath: Option<&str>) -> Mesh {
-        Mesh { tris, center, rotation: Vec3f::cons(0, 0, 0), texture: texpath.map(Texture::build_from_file) }
+    /// material 0 is always the default
+    pub fn cons(tris: Vec<Tri>, center: Vec3f) -> Mesh {
+        let groups = vec![MeshGroup { object: String::new(), name: "default".to_string(), tris: 0..tris.len() }];
+        let mut mesh = Mesh {
+            tris,
+            groups,
+            transform: Transform::cons(center),
+            materials: vec![Material::default()],
+            cull: CullMode::Back,
+            bounds: Aabb::empty(),
+            sphere: Sphere::cons(Vec3f::cons(0, 0, 0), 0.),
+            chunks: Vec::new(),
+            bvh: Bvh::default(),
+        };
+        mesh.update_bounds();
+        mesh
+    }
+
+    /// chunks are runs of consecutive triangles, which are usually close together
+    pub fn update_bounds(&mut self) {
+        self.bounds = Aabb::from_tris(&self.tris);
+        self.sphere = Sphere::from_tris(&self.tris, &self.bounds);
+        self.chunks = if self.tris.len() > CHUNK_SIZE {
+            (0..self.tris.len())
+                .step_by(CHUNK_SIZE)
+                .map(|start| Chunk::cons(&self.tris, start..(start + CHUNK_SIZE).min(self.tris.len())))
+                .collect()
+        }
+        else {
+            Vec::new()
+        };
+        self.bvh = Bvh::build(&self.tris);
+    }
+
+    /// centers the bounds and scales the bounding sphere to `radius`
+    pub fn fit_to(&mut self, radius: Float) {
+        let (center, scale) = (self.bounds.center(), radius / self.sphere.radius.max(Float::EPSILON));
+        for tri in &mut self.tris {
+            for vert in [&mut tri.a, &mut tri.b, &mut tri.c] {
+                vert.pos = (vert.pos - center) * scale;
+            }
+        }
+        self.update_bounds();
     }
 
-    pub fn build_from_file(path: &str, scaling: Float) -> Mesh {
-        let data = read_to_string(path).unwrap();
-        let mut vertices = Vec::new();
-        let mut tris = Vec::new();
+    /// model space, `t` is in multiples of the ray's direction
+    pub fn closest_hit(&self, ray: &Ray) -> Option<Hit> {
+        self.bvh.closest(&self.tris, ray)
+    }
 
-        for line in data.lines() {
-            let parts: Vec<&str> = line.split_whitespace().collect();
-            if parts.is_empty() {
-                continue;
-            }
+    pub fn any_hit(&self, ray: &Ray, max_t: Float) -> bool {
+        self.bvh.any(&self.tris, ray, max_t)
+    }
 
-            match parts[0] {
-                "v" => {
-                    let x: Float = parts[1].parse::<Float>().unwrap() * scaling;
-                    let y: Float = parts[2].parse::<Float>().unwrap() * scaling;
-                    let z: Float = parts[3].parse::<Float>().unwrap() * scaling;
-                    vertices.push(Vec3f::cons(x, y, z));
-                }
-                "f" => {
-                    let i0: usize = parts[1].parse().unwrap();
-                    let i1: usize = parts[2].parse().unwrap();
-                    let i2: usize = parts[3].parse().unwrap();
+    pub fn all_hits(&self, ray: &Ray) -> Vec<Hit> {
+        self.bvh.all(&self.tris, ray)
+    }
 
-                    tris.push(Tri::cons_pos(vertices[i0 - 1], vertices[i1 - 1], vertices[i2 - 1]));
+    /// for models exported with clockwise front faces
+    pub fn flip_winding(&mut self) {
+        for tri in &mut self.tris {
+            swap(&mut tri.b, &mut tri.c);
+            let normal = tri.get_normal();
+            for vert in [&mut tri.a, &mut tri.b, &mut tri.c] {
+                if vert.has_normal() && vert.normal.inner_prod(&normal) < 0. {
+                    vert.normal = -vert.normal;
                 }
-                _ => {}
             }
         }
-
-        Mesh::cons(tris, Vec3f::cons(0, 0, 0), None)
     }
 
-    pub fn build_from_file_extended(path: &str, scaling: Float, texpath: Option<&str>) -> Mesh {
-        let data = read_to_string(path).unwrap();
-        let mut vertices = Vec::new();
-        let mut tris = Vec::new();
-        let mut tex_coords = Vec::new();
-
-        for line in data.lines() {
-            let parts: Vec<&str> = line.split_whitespace().collect();
-            if parts.is_empty() {
-                continue;
-            };
+    /// a material library that can't be read only warns, `texpath` overrides every diffuse map
+    pub fn build_from_file(path: &str, scaling: Float, texpath: Option<&str>) -> Result<Mesh, ObjError> {
+        let mut data = ObjData::load(path)?;
+        let directory = Path::new(path).parent().unwrap_or(Path::new(""));
 
-            match parts[0] {
-                "v" => {
-                    let x: Float = parts[1].parse::<Float>().unwrap() * scaling;
-                    let y: Float = parts[2].parse::<Float>().unwrap() * scaling;
-                    let z: Float = parts[3].parse::<Float>().unwrap() * scaling;
-                    vertices.push(Vec3f::cons(x, y, z));
+        let mut library = Vec::new();
+        let mut warnings = take(&mut data.warnings);
+        for (line, file) in &data.material_libs {
+            let libpath = directory.join(file).to_string_lossy().to_string();
+            match Material::build_library_from_file(&libpath) {
+                Ok((materials, lib_warnings)) => {
+                    library.extend(materials);
+                    warnings.extend(lib_warnings);
                 }
-                "vt" => {
-                    let u: Float = parts[1].parse().unwrap();
-                    let v: Float = parts[2].parse::<Float>().unwrap() * -1. + 1.;
-                    tex_coords.push(Vec2f::cons(u, v));
+                Err(ObjError::Io { error, .. }) => {
+                    let message = format!("material library '{}' skipped: {}", libpath, error);
+                    warnings.push(ObjWarning { path: path.to_string(), line: *line, message });
                 }
-                "f" => {
-                    let mut face_vertices = Vec::new();
-                    for part in parts.iter().skip(1) {
-                        let indices: Vec<&str> = part.split('/').collect();
-                        let vert_idx = indices[0].parse::<usize>().unwrap() - 1;
-                        let vert = vertices[vert_idx];
-
-                        let tex_coord = if indices.len() > 1 && !indices[1].is_empty() {
-                            let tex_idx = indices[1].parse::<usize>().unwrap() - 1;
-                            tex_coords[tex_idx]
+                Err(error) => return Err(error),
+            }
+        }
+        for warning in &warnings {
+            eprintln!("{}", warning);
+        }
+
+        let mut mesh = Mesh::build_from_obj(&data, library, scaling);
+        if let Some(texpath) = texpath {
+            let texture = Texture::build_from_file(texpath)
+                .map_err(|error| ObjError::Texture { path: texpath.to_string(), message: error.to_string() })?;
+            let texture = Arc::new(texture);
+            mesh.materials.iter_mut().for_each(|material| {
+                material.diffuse_map = Some(Arc::clone(&texture));
+                material.infer_transparency();
+            });
+        }
+
+        Ok(mesh)
+    }
+
+    /// faces without uvs, vertex colors or a material get the red/green/blue debug colors
+    pub fn build_from_obj(data: &ObjData, library: Vec<Material>, scaling: Float) -> Mesh {
+        let material_lookup: Vec<usize> = data
+            .material_names
+            .iter()
+            .map(|name| library.iter().position(|material| material.name == *name).map_or(0, |idx| idx + 1))
+            .collect();
+
+        let mut tris = Vec::with_capacity(data.face_count());
+        let mut groups = Vec::with_capacity(data.groups.len());
+
+        for group in &data.groups {
+            let first = tris.len();
+            for face in &group.faces {
+                let material = face.material.map_or(0, |idx| material_lookup[idx]);
+                let untextured = face.material.is_none()
+                    && face.verts.iter().all(|idx| idx.tex.is_none() && data.colors[idx.pos].is_none());
+                let verts: Vec<Vert> = face
+                    .verts
+                    .iter()
+                    .map(|idx| {
+                        let pos = data.positions[idx.pos] * scaling;
+                        let texpos = idx.tex.map(|tex| data.texcoords[tex]).unwrap_or(Vec2f::cons(0, 0));
+                        let color = data.colors[idx.pos].unwrap_or_default();
+                        let mut vert = Vert::cons(pos, color, texpos);
+                        if let Some(normal) = idx.normal {
+                            vert.normal = data.normals[normal].get_normalized();
                         }
-                        else {
-                            Vec2f::cons(0, 0)
-                        };
-                        face_vertices.push((vert, tex_coord));
+                        vert
+                    })
+                    .collect();
+
+                for i in 2..verts.len() {
+                    let mut tri = Tri::cons_vert(verts[0], verts[i - 1], verts[i]);
+                    tri.material = material;
+                    tri.smoothing = face.smoothing;
+                    if untextured {
+                        let debug = Tri::cons_pos(tri.a.pos, tri.b.pos, tri.c.pos);
+                        tri.a.color = debug.a.color;
+                        tri.b.color = debug.b.color;
+                        tri.c.color = debug.c.color;
                     }
+                    tris.push(tri);
+                }
+            }
+            groups.push(MeshGroup { object: group.object.clone(), name: group.name.clone(), tris: first..tris.len() });
+        }
+
+        let mut mesh = Mesh::cons(tris, Vec3f::cons(0, 0, 0));
+        mesh.groups = groups;
+        mesh.materials.extend(library);
+        mesh.generate_normals(CREASE_ANGLE, false);
+        mesh
+    }
+
+    /// area weighted within a smoothing group and `crease_angle`, group 0 stays flat
+    pub fn generate_normals(&mut self, crease_angle: Float, overwrite: bool) {
+        let faces: Vec<Vec3f> =
+            self.tris.iter().map(|tri| (tri.a.pos - tri.b.pos).cross(&(tri.a.pos - tri.c.pos))).collect();
+        let units: Vec<Vec3f> = faces.iter().map(Vec3f::get_normalized).collect();
 
-                    for i in 2..face_vertices.len() {
-                        let (v0, t0) = face_vertices[0];
-                        let (v1, t1) = face_vertices[i - 1];
-                        let (v2, t2) = face_vertices[i];
+        let key = |pos: &Vec3f, smoothing: Option<u32>| (pos.x.to_bits(), pos.y.to_bits(), pos.z.to_bits(), smoothing);
+        let mut shared: HashMap<_, Vec<usize>> = HashMap::new();
+        for (idx, tri) in self.tris.iter().enumerate() {
+            for vert in [&tri.a, &tri.b, &tri.c] {
+                shared.entry(key(&vert.pos, tri.smoothing)).or_default().push(idx);
+            }
+        }
 
-                        let v0 = Vert::cons(v0, Color::cons(255, 255, 255), t0);
-                        let v1 = Vert::cons(v1, Color::cons(255, 255, 255), t1);
-                        let v2 = Vert::cons(v2, Color::cons(255, 255, 255), t2);
+        let threshold = crease_angle.cos();
+        for (idx, tri) in self.tris.iter_mut().enumerate() {
+            let smoothing = tri.smoothing;
+            for vert in [&mut tri.a, &mut tri.b, &mut tri.c] {
+                if vert.has_normal() && !overwrite {
+                    continue;
+                }
 
-                        tris.push(Tri::cons_vert(v0, v1, v2));
+                let mut normal = Vec3f::cons(0, 0, 0);
+                let neighbours = match smoothing {
+                    Some(0) => &[idx][..],
+                    _ => &shared[&key(&vert.pos, smoothing)],
+                };
+                for &neighbour in neighbours {
+                    if units[idx].inner_prod(&units[neighbour]) >= threshold {
+                        normal += faces[neighbour];
                     }
                 }
-                _ => {}
+                vert.normal = if normal.inner_prod(&normal) > 0. {
+                    normal.get_normalized()
+                }
+                else {
+                    Vec3f::cons(0, 0, 0)
+                };
             }
         }
-
-        Mesh::cons(tris, Vec3f::cons(0, 0, 0), texpath)
     }
 
     pub fn rotate_x(&mut self, angle: Float) {
-        self.rotation.x += angle;
+        self.transform.rotate_local(Vec3f::cons(1, 0, 0), angle);
     }
 
     pub fn rotate_y(&mut self, angle: Float) {
-        self.rotation.y += angle;
+        self.transform.rotate_local(Vec3f::cons(0, 1, 0), angle);
     }
 
     pub fn rotate_z(&mut self, angle: Float) {
-        self.rotation.z += angle;
+        self.transform.rotate_local(Vec3f::cons(0, 0, 1), angle);
+    }
+
+    pub fn model_matrix(&self) -> Mat4 {
+        self.transform.matrix()
     }
 }
 
@@ -280,6 +547,7 @@ pub struct BarycentricSystem<'d> {
     b: Vec3f,
     c: Vec3f,
     inv_den: Float,
+    inv_depths: Vec3f,
     bc_y: Float,
     cb_x: Float,
     ca_y: Float,
@@ -287,7 +555,7 @@ pub struct BarycentricSystem<'d> {
 }
 
 impl BarycentricSystem<'_> {
-    pub fn cons(triangle: &Tri) -> BarycentricSystem {
+    pub fn cons(triangle: &Tri) -> BarycentricSystem<'_> {
         let a = triangle.a.pos;
         let b = triangle.b.pos;
         let c = triangle.c.pos;
@@ -300,6 +568,7 @@ impl BarycentricSystem<'_> {
             b,
             c,
             inv_den,
+            inv_depths: Vec3f::cons(1. / a.z, 1. / b.z, 1. / c.z),
             bc_y: b.y - c.y,
             cb_x: c.x - b.x,
             ca_y: c.y - a.y,
@@ -308,14 +577,27 @@ impl BarycentricSystem<'_> {
     }
 
     pub fn get_coords(&self, x: Int, y: Int) -> Vec3f {
-        let x = x as Float;
-        let y = y as Float;
+        self.get_coords_at(x as Float, y as Float)
+    }
 
+    /// same as `get_coords` for points between pixels, like multisample positions
+    pub fn get_coords_at(&self, x: Float, y: Float) -> Vec3f {
         let w1 = (self.bc_y * (x - self.c.x) + self.cb_x * (y - self.c.y)) * self.inv_den;
         let w2 = (self.ca_y * (x - self.c.x) + self.ac_x * (y - self.c.y)) * self.inv_den;
 
         Vec3f::cons(w1, w2, 1. - w1 - w2)
     }
+
+    /// weighting each vert by its 1/z and renormalizing makes the weights perspective correct
+    #[inline]
+    pub fn perspective_correct(&self, coords: &Vec3f) -> Vec3f {
+        let weighted = Vec3f::cons(
+            coords.x * self.inv_depths.x,
+            coords.y * self.inv_depths.y,
+            coords.z * self.inv_depths.z,
+        );
+        weighted / (weighted.x + weighted.y + weighted.z)
+    }
 }
 
 #[derive(Clone, Copy)]
@@ -333,3 +615,161 @@ impl RefFrame {
         self.center += translation;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// two faces folded about 27 degrees along a shared edge, well under the crease angle
+    fn folded(first: &str, second: &str) -> [Tri; 2] {
+        let source = format!("v 0 0 0\nv 0 1 0\nv 1 0 0\nv -1 0 0.5\n{}f 1 3 2\n{}f 1 2 4\n", first, second);
+        let data = ObjData::parse(&source, "test.obj").unwrap();
+        let mesh = Mesh::build_from_obj(&data, Vec::new(), 1.);
+        [mesh.tris[0], mesh.tris[1]]
+    }
+
+    fn is_flat(tri: &Tri) -> bool {
+        let face = (tri.a.pos - tri.b.pos).cross(&(tri.a.pos - tri.c.pos)).get_normalized();
+        let off = tri.a.normal - face;
+        off.inner_prod(&off) < 1e-6
+    }
+
+    /// the near plane at x = 1, the camera looks down +x
+    const NEAR: [Plane; 1] = [Plane { normal: Vec3f { x: 1., y: 0., z: 0. }, offset: -1. }];
+
+    /// `world` is kept apart from `pos` so the test can tell they're both carried through
+    fn vert(x: Float, y: Float, z: Float, shade: Float) -> Vert {
+        let mut vert = Vert::cons(Vec3f::cons(x, y, z), Color::cons(shade, 255. - shade, 40.), Vec2f::cons(y, z));
+        vert.world = Vec3f::cons(x * 10., y - 3., z + 7.);
+        vert
+    }
+
+    fn same(lhs: &Vert, rhs: &Vert) -> bool {
+        let close = |lhs: Float, rhs: Float| (lhs - rhs).abs() < 1e-4;
+        let (lw, rw) = (lhs.world, rhs.world);
+        close(lw.x, rw.x)
+            && close(lw.y, rw.y)
+            && close(lw.z, rw.z)
+            && close(lhs.texpos.x, rhs.texpos.x)
+            && close(lhs.texpos.y, rhs.texpos.y)
+            && close(lhs.color.red, rhs.color.red)
+            && close(lhs.color.green, rhs.color.green)
+            && close(lhs.color.blue, rhs.color.blue)
+    }
+
+    /// every output vert is either an input vert in front or one of the two crossings
+    fn check_pieces(tri: &Tri, behind: &[Vert], front: &[Vert], pieces: &[Tri]) {
+        let mut crossings = Vec::new();
+        for from in behind {
+            for to in front {
+                let t = NEAR[0].distance(&from.pos) / (NEAR[0].distance(&from.pos) - NEAR[0].distance(&to.pos));
+                crossings.push(from.lerp(to, t));
+            }
+        }
+
+        let mut seen = vec![false; crossings.len()];
+        for piece in pieces {
+            assert_eq!(piece.material, tri.material);
+            for out in [&piece.a, &piece.b, &piece.c] {
+                assert!(out.pos.x >= 1. - 1e-5, "{:?} is behind the plane", out.pos);
+                if front.iter().any(|vert| same(vert, out)) {
+                    continue;
+                }
+                assert!((out.pos.x - 1.).abs() < 1e-5, "{:?} should be on the plane", out.pos);
+                let crossing = crossings.iter().position(|crossing| same(crossing, out));
+                let crossing = crossing.unwrap_or_else(|| panic!("{:?} isn't a crossing", out.world));
+                seen[crossing] = true;
+            }
+        }
+        assert!(seen.iter().all(|seen| *seen));
+    }
+
+    #[test]
+    fn one_vert_behind_near_clips_to_two_pieces() {
+        let (a, b, c) = (vert(-1., 0., 0., 0.), vert(3., 2., 0., 100.), vert(3., 0., 2., 200.));
+        let mut tri = Tri::cons_vert(a, b, c);
+        tri.material = 4;
+        let pieces = tri.clip(&NEAR);
+        assert_eq!(pieces.len(), 2);
+        check_pieces(&tri, &[a], &[b, c], &pieces);
+    }
+
+    #[test]
+    fn two_verts_behind_near_clips_to_one_piece() {
+        let (a, b, c) = (vert(-1., 0., 0., 0.), vert(0., 2., 0., 100.), vert(5., 0., 2., 200.));
+        let mut tri = Tri::cons_vert(a, b, c);
+        tri.material = 2;
+        let pieces = tri.clip(&NEAR);
+        assert_eq!(pieces.len(), 1);
+        check_pieces(&tri, &[a, b], &[c], &pieces);
+    }
+
+    #[test]
+    fn clip_keeps_front_and_drops_behind() {
+        let front = Tri::cons_vert(vert(2., 0., 0., 0.), vert(3., 2., 0., 100.), vert(1., 0., 2., 200.));
+        let pieces = front.clip(&NEAR);
+        assert_eq!(pieces.len(), 1);
+        for (out, vert) in [(pieces[0].a, front.a), (pieces[0].b, front.b), (pieces[0].c, front.c)] {
+            assert_eq!((out.pos.x, out.pos.y, out.pos.z), (vert.pos.x, vert.pos.y, vert.pos.z));
+            assert!(same(&out, &vert));
+        }
+
+        let behind = Tri::cons_vert(vert(0.5, 0., 0., 0.), vert(-3., 2., 0., 100.), vert(0., 0., 2., 200.));
+        assert!(behind.clip(&NEAR).is_empty());
+    }
+
+    #[test]
+    fn cull_modes_against_both_windings() {
+        let (a, b, c) = (Vec3f::cons(10., 10., 0.5), Vec3f::cons(30., 10., 0.5), Vec3f::cons(10., 40., 0.5));
+        let counter_clockwise = Tri::cons_pos(a, b, c);
+        let clockwise = Tri::cons_pos(a, c, b);
+        let degenerate = Tri::cons_pos(a, b, Vec3f::cons(50., 10., 0.5));
+        assert!(counter_clockwise.screen_area() > 0. && clockwise.screen_area() < 0.);
+        assert_eq!(degenerate.screen_area(), 0.);
+
+        for (mode, culled) in [
+            (CullMode::None, [false, false, false]),
+            (CullMode::Back, [false, true, false]),
+            (CullMode::Front, [true, false, false]),
+        ] {
+            let found = [&counter_clockwise, &clockwise, &degenerate].map(|tri| mode.culls(tri.screen_area()));
+            assert_eq!(found, culled, "{:?}", mode);
+        }
+    }
+
+    #[test]
+    fn flip_winding_reverses_order_and_normals() {
+        let original = folded("", "");
+        let mut mesh = Mesh::cons(original.to_vec(), Vec3f::cons(0, 0, 0));
+        let close = |lhs: Vec3f, rhs: Vec3f| (lhs - rhs).inner_prod(&(lhs - rhs)) < 1e-10;
+
+        mesh.flip_winding();
+        for (flipped, tri) in mesh.tris.iter().zip(&original) {
+            for (out, vert) in [(flipped.a, tri.a), (flipped.b, tri.c), (flipped.c, tri.b)] {
+                assert!(close(out.pos, vert.pos) && close(out.normal, -vert.normal));
+            }
+            assert!(close(flipped.get_normal(), -tri.get_normal()));
+        }
+
+        mesh.flip_winding();
+        for (restored, tri) in mesh.tris.iter().zip(&original) {
+            for (out, vert) in [(restored.a, tri.a), (restored.b, tri.b), (restored.c, tri.c)] {
+                assert!(close(out.pos, vert.pos) && close(out.normal, vert.normal));
+            }
+        }
+    }
+
+    #[test]
+    fn smoothing_groups_decide_what_gets_averaged() {
+        for (first, second) in [("", ""), ("s 1\n", ""), ("s 3\n", "s 3\n")] {
+            let tris = folded(first, second);
+            assert!(tris.iter().all(|tri| !is_flat(tri)), "{:?} {:?} stayed flat", first, second);
+            let gap = tris[0].a.normal - tris[1].a.normal;
+            assert!(gap.inner_prod(&gap) < 1e-6, "{:?} {:?} left a seam", first, second);
+        }
+        for (first, second) in [("s 1\n", "s 2\n"), ("s off\n", ""), ("s 0\n", ""), ("", "s off\n")] {
+            let tris = folded(first, second);
+            assert!(tris.iter().all(is_flat), "{:?} {:?} got smoothed", first, second);
+        }
+    }
+}
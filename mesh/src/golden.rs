@@ -0,0 +1,277 @@
+//! failures are written to `target/golden`, `GOLDEN_UPDATE=1` replaces the references
+
+use std::env;
+use std::fs::create_dir_all;
+use std::path::PathBuf;
+use std::time::Duration;
+use std::sync::Arc;
+
+use image::open;
+use image::Rgb;
+use image::RgbImage;
+
+use crate::geometry::CullMode;
+use crate::geometry::Mesh;
+use crate::geometry::Tri;
+use crate::geometry::Vert;
+use crate::geometry::CREASE_ANGLE;
+use crate::material::BlendMode;
+use crate::material::Material;
+use crate::math::Vec2f;
+use crate::math::Vec3f;
+use crate::render_utils::Antialias;
+use crate::render_utils::Buffer;
+use crate::render_utils::Camera;
+use crate::render_utils::Color;
+use crate::render_utils::Scene;
+use crate::renderer::RasterMode;
+use crate::renderer::RenderSettings;
+use crate::renderer::Renderer;
+use crate::texture::Texture;
+use crate::Float;
+use crate::PI;
+
+const WIDTH: usize = 160;
+const HEIGHT: usize = 120;
+const FOV: Float = 90.;
+
+/// how far apart a channel can be before the pixel counts as different
+const TOLERANCE: u8 = 8;
+/// optimized builds round a few edge pixels the other way
+const MAX_MISMATCH: Float = 0.001;
+
+fn render(scene: &mut Scene, camera: &Camera, settings: RenderSettings) -> RgbImage {
+    let mut buffer = Buffer::cons(HEIGHT, WIDTH);
+    if settings.shadows {
+        scene.update_shadows();
+    }
+    Renderer::cons(&mut buffer, scene, camera, FOV, settings).render_scene();
+    buffer.resolve();
+    buffer.to_image()
+}
+
+/// at a size none of the tile sizes below divide, so there are edge tiles
+fn render_buffer(scene: &Scene, camera: &Camera, settings: RenderSettings, antialias: Antialias) -> Buffer {
+    let mut buffer = Buffer::cons_antialiased(101, 157, antialias);
+    Renderer::cons(&mut buffer, scene, camera, FOV, settings).render_scene();
+    buffer.resolve();
+    buffer
+}
+
+fn golden_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests").join("golden")
+}
+
+fn failure_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("target").join("golden")
+}
+
+/// mismatched pixels in red over a dimmed copy of the reference
+fn diff_image(expected: &RgbImage, actual: &RgbImage) -> (RgbImage, usize) {
+    let mut mismatched = 0;
+    let diff = RgbImage::from_fn(expected.width(), expected.height(), |x, y| {
+        let (lhs, rhs) = (expected.get_pixel(x, y), actual.get_pixel(x, y));
+        let close = lhs.0.iter().zip(rhs.0.iter()).all(|(lhs, rhs)| lhs.abs_diff(*rhs) <= TOLERANCE);
+        if close {
+            Rgb(lhs.0.map(|channel| channel / 4))
+        }
+        else {
+            mismatched += 1;
+            Rgb([255, 0, 0])
+        }
+    });
+    (diff, mismatched)
+}
+
+fn check(name: &str, actual: &RgbImage) {
+    let path = golden_dir().join(format!("{}.png", name));
+    if env::var_os("GOLDEN_UPDATE").is_some() {
+        create_dir_all(golden_dir()).unwrap();
+        actual.save(&path).unwrap();
+        return;
+    }
+
+    let expected = match open(&path) {
+        Ok(image) => image.to_rgb8(),
+        Err(error) => panic!("no reference for '{}' at {:?} ({}), run with GOLDEN_UPDATE=1", name, path, error),
+    };
+    assert_eq!(expected.dimensions(), actual.dimensions(), "'{}' rendered at the wrong size", name);
+
+    let (diff, mismatched) = diff_image(&expected, actual);
+    let allowed = (MAX_MISMATCH * (WIDTH * HEIGHT) as Float) as usize;
+    if mismatched > allowed {
+        create_dir_all(failure_dir()).unwrap();
+        let (actual_path, diff_path) =
+            (failure_dir().join(format!("{}.png", name)), failure_dir().join(format!("{}_diff.png", name)));
+        actual.save(&actual_path).unwrap();
+        diff.save(&diff_path).unwrap();
+        panic!(
+            "'{}' differs from the reference in {} pixels (allowed {}), see {:?} and {:?}",
+            name, mismatched, allowed, actual_path, diff_path
+        );
+    }
+}
+
+/// no culling so winding doesn't decide what shows up
+fn mesh(tris: Vec<Tri>) -> Mesh {
+    let mut mesh = Mesh::cons(tris, Vec3f::cons(0, 0, 0));
+    mesh.generate_normals(CREASE_ANGLE, true);
+    mesh.cull = CullMode::None;
+    mesh
+}
+
+/// a quad facing the camera at distance `x`, split along one diagonal
+fn quad(x: Float, left: Float, right: Float, bottom: Float, top: Float, color: Color) -> [Tri; 2] {
+    let vert = |y: Float, z: Float, u: Float, v: Float| Vert::cons(Vec3f::cons(x, y, z), color, Vec2f::cons(u, v));
+    let (bottom_left, bottom_right) = (vert(left, bottom, 0., 0.), vert(right, bottom, 2., 0.));
+    let (top_left, top_right) = (vert(left, top, 0., 2.), vert(right, top, 2., 2.));
+    [Tri::cons_vert(bottom_left, bottom_right, top_right), Tri::cons_vert(bottom_left, top_right, top_left)]
+}
+
+fn checkerboard(size: usize, cell: usize) -> Texture {
+    let texels = (0..size * size)
+        .map(|idx| match ((idx % size) / cell + (idx / size) / cell) % 2 {
+            0 => Color::cons(230, 60, 40),
+            _ => Color::cons(30, 40, 200),
+        })
+        .collect();
+    Texture::cons(size, size, texels)
+}
+
+fn load_portal() -> Mesh {
+    let path = format!("{}/portal/portal.obj", env!("CARGO_MANIFEST_DIR"));
+    let texture = format!("{}/portal/portal_tex.jpg", env!("CARGO_MANIFEST_DIR"));
+    let mut portal = Mesh::build_from_file(&path, 55., Some(&texture)).unwrap();
+    portal.transform.position = Vec3f::cons(0, 0, 0);
+    portal.rotate_x(PI / 2.);
+    portal
+}
+
+fn origin_camera() -> Camera {
+    Camera::cons(Vec3f::cons(0, 0, 0))
+}
+
+#[test]
+fn single_triangle() {
+    let tri = Tri::cons_pos(Vec3f::cons(10, 6, -5), Vec3f::cons(10, -7, -4), Vec3f::cons(10, 1, 6));
+    let mut scene = Scene::new();
+    scene.add("triangle", mesh(vec![tri]));
+    let settings = RenderSettings { shadows: false, ..Default::default() };
+    check("single_triangle", &render(&mut scene, &origin_camera(), settings));
+}
+
+#[test]
+fn single_triangle_edge_function() {
+    let tri = Tri::cons_pos(Vec3f::cons(10, 6, -5), Vec3f::cons(10, -7, -4), Vec3f::cons(10, 1, 6));
+    let mut scene = Scene::new();
+    scene.add("triangle", mesh(vec![tri]));
+    let settings = RenderSettings { shadows: false, raster: RasterMode::EdgeFunction, ..Default::default() };
+    check("single_triangle_edge", &render(&mut scene, &origin_camera(), settings));
+}
+
+/// turned away so the checkers show off perspective correction
+#[test]
+fn textured_quad() {
+    let white = Color::cons(255, 255, 255);
+    let mut tris = quad(0., -4., 4., -4., 4., white).to_vec();
+    tris.iter_mut().for_each(|tri| tri.material = 1);
+    let mut quad = mesh(tris);
+    quad.rotate_z(PI / 4.);
+    quad.transform.position = Vec3f::cons(8, 0, 0);
+
+    let mut material = Material::cons("checkers");
+    material.diffuse_map = Some(Arc::new(checkerboard(64, 16)));
+    quad.materials.push(material);
+
+    let mut scene = Scene::new();
+    scene.add("quad", quad);
+    let settings = RenderSettings { shadows: false, ..Default::default() };
+    check("textured_quad", &render(&mut scene, &origin_camera(), settings));
+}
+
+#[test]
+fn portal() {
+    let mut scene = Scene::new();
+    scene.add("portal", load_portal());
+    let camera = Camera::cons(Vec3f::cons(-70, 0, 0));
+    check("portal", &render(&mut scene, &camera, RenderSettings::default()));
+
+    let settings = RenderSettings { threads: 4, tile_size: 32, ..Default::default() };
+    check("portal", &render(&mut scene, &camera, settings));
+
+    // the stage times come from the same pass that drew the frame
+    let mut buffer = Buffer::cons(HEIGHT, WIDTH);
+    let mut renderer = Renderer::cons(&mut buffer, &scene, &camera, FOV, settings);
+    renderer.render_scene();
+    let times = renderer.stage_times();
+    assert!(times.tris > 0 && times.polys > 0 && times.fill > Duration::ZERO, "{:?}", times);
+    buffer.resolve();
+    check("portal", &buffer.to_image());
+}
+
+/// pixels and depth have to come out the same however the frame is split up
+#[test]
+fn tiled_matches_single_threaded() {
+    let mut pane = mesh(quad(-40., -25., 5., -12., 8., Color::cons(60, 160, 220)).to_vec());
+    pane.tris.iter_mut().for_each(|tri| tri.material = 1);
+    let mut glass = Material::cons("glass");
+    glass.dissolve = 0.5;
+    glass.blend = BlendMode::Alpha;
+    pane.materials.push(glass);
+
+    let mut scene = Scene::new();
+    scene.add("portal", load_portal());
+    scene.add("pane", pane);
+    scene.update_shadows();
+    let camera = Camera::cons(Vec3f::cons(-70, 0, 0));
+
+    for raster in [RasterMode::Bresenham, RasterMode::EdgeFunction] {
+        for antialias in [Antialias::Off, Antialias::Multisample(4), Antialias::Supersample(2)] {
+            let single = RenderSettings { raster, ..Default::default() };
+            let expected = render_buffer(&scene, &camera, single, antialias);
+            for (threads, tile_size) in [(2, 7), (3, 32), (4, 50), (8, 64)] {
+                let settings = RenderSettings { threads, tile_size, ..single };
+                let actual = render_buffer(&scene, &camera, settings, antialias);
+                let case =
+                    format!("{:?} {:?} with {} threads and {} pixel tiles", raster, antialias, threads, tile_size);
+                assert!(expected.get_pixels() == actual.get_pixels(), "pixels differ, {}", case);
+                for y in 0..expected.height {
+                    for x in 0..expected.width {
+                        let (lhs, rhs) = (expected.get_depth(x, y), actual.get_depth(x, y));
+                        assert!(lhs.to_bits() == rhs.to_bits(), "depth differs at {},{}, {}", x, y, case);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// the far quad has to lose the depth test rather than paint over the near one
+#[test]
+fn depth_order() {
+    let mut tris = quad(8., -6., 2., -5., 3., Color::cons(220, 40, 40)).to_vec();
+    tris.extend(quad(14., -4., 9., -7., 7., Color::cons(40, 200, 60)));
+    let mut scene = Scene::new();
+    scene.add("quads", mesh(tris));
+    let settings = RenderSettings { shadows: false, ..Default::default() };
+    check("depth_order", &render(&mut scene, &origin_camera(), settings));
+}
+
+/// the crossing line comes from per pixel depth alone
+#[test]
+fn depth_intersect() {
+    let first = Tri::cons_vert(
+        Vert::cons(Vec3f::cons(6, 8, -6), Color::cons(220, 40, 40), Vec2f::cons(0, 0)),
+        Vert::cons(Vec3f::cons(14, -8, -6), Color::cons(220, 40, 40), Vec2f::cons(0, 0)),
+        Vert::cons(Vec3f::cons(10, 0, 7), Color::cons(220, 40, 40), Vec2f::cons(0, 0)),
+    );
+    let second = Tri::cons_vert(
+        Vert::cons(Vec3f::cons(14, 8, -5), Color::cons(40, 80, 220), Vec2f::cons(0, 0)),
+        Vert::cons(Vec3f::cons(6, -8, -5), Color::cons(40, 80, 220), Vec2f::cons(0, 0)),
+        Vert::cons(Vec3f::cons(10, 0, 6), Color::cons(40, 80, 220), Vec2f::cons(0, 0)),
+    );
+    let mut scene = Scene::new();
+    scene.add("crossing", mesh(vec![first, second]));
+    let settings = RenderSettings { shadows: false, ..Default::default() };
+    check("depth_intersect", &render(&mut scene, &origin_camera(), settings));
+}
@@ -0,0 +1,281 @@
+use crate::material::Material;
+use crate::math::Vec3f;
+use crate::render_utils::Color;
+use crate::shadow::ShadowMap;
+use crate::shadow::ShadowSettings;
+use crate::Float;
+
+/// light falls off as 1 / (constant + linear * d + quadratic * d^2)
+#[derive(Debug, Clone, Copy)]
+pub struct Attenuation {
+    pub constant: Float,
+    pub linear: Float,
+    pub quadratic: Float,
+}
+
+impl Attenuation {
+    pub fn cons(constant: Float, linear: Float, quadratic: Float) -> Attenuation {
+        Attenuation { constant, linear, quadratic }
+    }
+
+    pub fn none() -> Attenuation {
+        Attenuation::cons(1., 0., 0.)
+    }
+
+    #[inline]
+    fn factor(&self, distance: Float) -> Float {
+        1. / (self.constant + self.linear * distance + self.quadratic * distance * distance).max(1e-6)
+    }
+
+    /// the distance where the light has dropped to `strength`
+    pub fn range(&self, strength: Float) -> Option<Float> {
+        let target = 1. / strength - self.constant;
+        if target <= 0. {
+            return Some(0.);
+        }
+        if self.quadratic > 0. {
+            let root = (self.linear * self.linear + 4. * self.quadratic * target).sqrt();
+            Some((root - self.linear) / (2. * self.quadratic))
+        }
+        else if self.linear > 0. {
+            Some(target / self.linear)
+        }
+        else {
+            None
+        }
+    }
+}
+
+/// world space, spot cones are half angles in radians
+#[derive(Debug, Clone, Copy)]
+pub enum LightKind {
+    Directional { direction: Vec3f },
+    Point { position: Vec3f, attenuation: Attenuation },
+    Spot { position: Vec3f, direction: Vec3f, inner: Float, outer: Float, attenuation: Attenuation },
+}
+
+/// only directional and spot lights cast shadows
+#[derive(Debug, Clone, Copy)]
+pub struct Light {
+    pub kind: LightKind,
+    pub color: Color,
+    pub intensity: Float,
+    pub shadow: Option<ShadowSettings>,
+}
+
+impl Light {
+    pub fn directional(direction: Vec3f, color: Color, intensity: Float) -> Light {
+        Light { kind: LightKind::Directional { direction: direction.get_normalized() }, color, intensity, shadow: None }
+    }
+
+    pub fn point(position: Vec3f, attenuation: Attenuation, color: Color, intensity: Float) -> Light {
+        Light { kind: LightKind::Point { position, attenuation }, color, intensity, shadow: None }
+    }
+
+    pub fn spot(
+        position: Vec3f, direction: Vec3f, inner: Float, outer: Float, attenuation: Attenuation, color: Color,
+        intensity: Float,
+    ) -> Light {
+        let direction = direction.get_normalized();
+        let kind = LightKind::Spot { position, direction, inner, outer, attenuation };
+        Light { kind, color, intensity, shadow: None }
+    }
+
+    #[inline]
+    fn incident(&self, point: Vec3f) -> (Vec3f, Float) {
+        match self.kind {
+            LightKind::Directional { direction } => (-direction, 1.),
+            LightKind::Point { position, attenuation } => {
+                let (to_light, distance) = direction_and_distance(position - point);
+                (to_light, attenuation.factor(distance))
+            }
+            LightKind::Spot { position, direction, inner, outer, attenuation } => {
+                let (to_light, distance) = direction_and_distance(position - point);
+                let (cos_inner, cos_outer) = (inner.cos(), outer.cos());
+                let cos_angle = (-to_light).inner_prod(&direction);
+                let cone = ((cos_angle - cos_outer) / (cos_inner - cos_outer).max(1e-6)).clamp(0., 1.);
+                (to_light, attenuation.factor(distance) * cone * cone * (3. - 2. * cone))
+            }
+        }
+    }
+}
+
+/// 0-1 per channel, `diffuse` includes the ambient term
+#[derive(Debug, Clone, Copy)]
+pub struct LightSample {
+    pub diffuse: Vec3f,
+    pub specular: Vec3f,
+}
+
+impl LightSample {
+    pub fn cons(diffuse: Vec3f, specular: Vec3f) -> LightSample {
+        LightSample { diffuse, specular }
+    }
+
+    pub fn add(&self, other: &LightSample) -> LightSample {
+        LightSample::cons(self.diffuse + other.diffuse, self.specular + other.specular)
+    }
+
+    pub fn scale(&self, factor: Float) -> LightSample {
+        LightSample::cons(self.diffuse * factor, self.specular * factor)
+    }
+
+    pub fn lerp3(samples: &[LightSample; 3], weights: &Vec3f) -> LightSample {
+        let mix = |pick: fn(&LightSample) -> Vec3f| {
+            pick(&samples[0]) * weights.x + pick(&samples[1]) * weights.y + pick(&samples[2]) * weights.z
+        };
+        LightSample { diffuse: mix(|sample| sample.diffuse), specular: mix(|sample| sample.specular) }
+    }
+
+    /// overall brightness, for things like the wireframe that only take one value
+    pub fn luminance(&self) -> Float {
+        (self.diffuse.x + self.diffuse.y + self.diffuse.z) / 3.
+    }
+
+    /// the result is linear and can go past 255, it gets tone mapped later
+    pub fn apply(&self, albedo: Color, specular: Color) -> Color {
+        Color::cons_rgba(
+            albedo.red * self.diffuse.x + specular.red * self.specular.x,
+            albedo.green * self.diffuse.y + specular.green * self.specular.y,
+            albedo.blue * self.diffuse.z + specular.blue * self.specular.z,
+            albedo.alpha,
+        )
+    }
+}
+
+impl Default for LightSample {
+    fn default() -> LightSample {
+        LightSample::cons(Vec3f::cons(0, 0, 0), Vec3f::cons(0, 0, 0))
+    }
+}
+
+/// per vertex shading lights the shadowed lights apart so they can be darkened per pixel
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum LightPass {
+    All,
+    Unshadowed,
+    Shadowed,
+}
+
+/// `shadow_maps` lines up with `lights` and is filled by `Scene::update_shadows`
+pub struct Lighting {
+    pub ambient: Color,
+    pub ambient_intensity: Float,
+    pub lights: Vec<Light>,
+    pub shadow_maps: Vec<Option<ShadowMap>>,
+}
+
+impl Lighting {
+    pub fn cons(ambient: Color, ambient_intensity: Float) -> Lighting {
+        Lighting { ambient, ambient_intensity, lights: Vec::new(), shadow_maps: Vec::new() }
+    }
+
+    pub fn add(&mut self, light: Light) {
+        self.lights.push(light);
+    }
+
+    pub fn has_shadows(&self) -> bool {
+        self.shadow_maps.iter().any(Option::is_some)
+    }
+
+    /// `normal` has to be unit length, only `All` gets darkened by shadow maps
+    pub fn shade(
+        &self, point: Vec3f, normal: Vec3f, eye: Vec3f, material: &Material, pass: LightPass, shadows: bool,
+    ) -> LightSample {
+        let mut diffuse = match pass {
+            LightPass::Shadowed => Vec3f::cons(0, 0, 0),
+            _ => channels(&self.ambient) * self.ambient_intensity,
+        };
+        let mut specular = Vec3f::cons(0, 0, 0);
+        let (to_eye, _) = direction_and_distance(eye - point);
+        let has_specular = material.specular.red + material.specular.green + material.specular.blue > 0.;
+
+        for (idx, light) in self.lights.iter().enumerate() {
+            let shadow_map = if shadows {
+                self.shadow_maps.get(idx).and_then(Option::as_ref)
+            }
+            else {
+                None
+            };
+            match pass {
+                LightPass::Unshadowed if shadow_map.is_some() => continue,
+                LightPass::Shadowed if shadow_map.is_none() => continue,
+                _ => (),
+            }
+
+            let (to_light, mut strength) = light.incident(point);
+            let lambert = normal.inner_prod(&to_light);
+            if lambert <= 0. || strength <= 0. {
+                continue;
+            }
+            if let Some(map) = shadow_map
+                && pass == LightPass::All
+            {
+                strength *= map.visibility(point, lambert);
+                if strength <= 0. {
+                    continue;
+                }
+            }
+
+            let radiance = channels(&light.color) * (light.intensity * strength);
+            diffuse += radiance * lambert;
+            if has_specular {
+                let half = (to_light + to_eye).get_normalized();
+                let highlight = normal.inner_prod(&half).max(0.).powf(material.shininess);
+                specular += radiance * highlight;
+            }
+        }
+
+        LightSample { diffuse, specular }
+    }
+
+    /// weighted by how strongly each shadowed light lights the point
+    pub fn visibility(&self, point: Vec3f, normal: Vec3f) -> Float {
+        let (mut visible, mut total) = (0., 0.);
+        for (light, map) in self.lights.iter().zip(&self.shadow_maps) {
+            let Some(map) = map
+            else {
+                continue;
+            };
+            let (to_light, strength) = light.incident(point);
+            let lambert = normal.inner_prod(&to_light);
+            let weight = light.intensity * strength * lambert;
+            if weight <= 0. {
+                continue;
+            }
+            visible += weight * map.visibility(point, lambert);
+            total += weight;
+        }
+        if total > 0. {
+            visible / total
+        }
+        else {
+            1.
+        }
+    }
+}
+
+/// the old hardcoded setup, one white light over the viewer's shoulder
+impl Default for Lighting {
+    fn default() -> Lighting {
+        let mut lighting = Lighting::cons(Color::cons(255, 255, 255), 0.15);
+        let mut key = Light::directional(Vec3f::cons(3, -1, 4), Color::cons(255, 255, 255), 0.85);
+        key.shadow = Some(ShadowSettings::default());
+        lighting.add(key);
+        lighting
+    }
+}
+
+#[inline]
+fn channels(color: &Color) -> Vec3f {
+    color.as_vec3f() / 255.
+}
+
+#[inline]
+fn direction_and_distance(delta: Vec3f) -> (Vec3f, Float) {
+    let distance = delta.inner_prod(&delta).sqrt();
+    if distance <= 0. {
+        return (Vec3f::cons(0, 0, 0), 0.);
+    }
+    (delta / distance, distance)
+}
@@ -1,28 +1,54 @@
 #![allow(clippy::approx_constant)]
 #![allow(dead_code)]
 
+mod bench;
+mod bounds;
+mod bvh;
+mod config;
 mod geometry;
+#[cfg(test)]
+mod golden;
+mod lighting;
+mod material;
 mod math;
+mod obj;
 mod render_utils;
 mod renderer;
+mod shadow;
+#[cfg(test)]
+mod testing;
 mod texture;
+mod tiles;
 mod utils;
 
+use std::env::args;
+use std::process::exit;
 use std::time::Instant;
 
 use minifb::Key;
 use minifb::Scale;
 
+use bench::run_benchmarks;
+use config::Command;
+use config::Config;
+use config::USAGE;
 use geometry::RefFrame;
 use math::Vec3f;
 use render_utils::Buffer;
-use render_utils::Camera;
+use renderer::RenderSettings;
 use renderer::Renderer;
+use utils::describe_culling;
+use utils::describe_pick;
 use utils::handle_camera_input;
 use utils::handle_mutation_input;
+use utils::handle_pick_input;
 use utils::handle_renderer_input;
-use utils::make_mesh;
+use utils::handle_settings_input;
+use utils::make_camera;
+use utils::make_scene;
 use utils::make_window;
+use utils::render_headless;
+use utils::MAIN_OBJECT;
 
 type Float = f32;
 type Int = i32;
@@ -30,37 +56,85 @@ type Int = i32;
 const PI: Float = 3.141592;
 const TAU: Float = 2. * PI;
 
-const FOV: Float = 90.;
-const FPS: usize = 120;
 const BACKGROUND: u32 = 0xffbbbbbb;
-const RESMOD: usize = 1;
-const HEIGHT: usize = 1200 / RESMOD;
-const WIDTH: usize = 2560 / RESMOD;
 
 #[allow(unused_variables, unused_mut)]
 fn main() {
     unsafe {
         std::env::set_var("RUST_BACKTRACE", "full");
     }
-    let mut buffer = Buffer::cons(HEIGHT, WIDTH);
-    let mut window = make_window(&buffer, FPS, Scale::X1);
-    let mut mesh = make_mesh();
-    let mut camera = Camera::cons(Vec3f::cons(-100, 0, 0));
+    let config = match Config::build_from_args(args()) {
+        Ok(Command::Run(config)) => config,
+        Ok(Command::Help) => {
+            println!("{}", USAGE);
+            exit(0);
+        }
+        Err(message) => {
+            eprintln!("{}", message);
+            exit(2);
+        }
+    };
+
+    let mut buffer = Buffer::cons_antialiased(config.height, config.width, config.antialias);
+    buffer.set_background(config.background);
+    let mut scene = make_scene(&config);
+    if let Some(mesh) = scene.get_mut(MAIN_OBJECT) {
+        mesh.cull = config.cull;
+        if config.flip_winding {
+            mesh.flip_winding();
+        }
+    }
+    let mut camera = make_camera(&config, &scene);
+    let mut settings = RenderSettings {
+        threads: config.threads,
+        raster: config.raster,
+        filter: config.filter,
+        shading: config.shading,
+        normals: config.normals,
+        shadows: config.shadows,
+        tone_map: config.tone_map,
+        view: config.view,
+        ..Default::default()
+    };
+    if let Some(frames) = config.bench {
+        run_benchmarks(&config, settings, frames);
+        return;
+    }
+    if let Some(path) = &config.headless {
+        if settings.shadows {
+            scene.update_shadows();
+        }
+        render_headless(path, &mut buffer, &scene, &camera, config.fov, settings);
+        return;
+    }
+
+    let mut window = make_window(&buffer, config.fps, Scale::X1);
     let mut mouse = None;
+    let mut picked = None;
     let frame = RefFrame::cons(Vec3f::cons(0, 0, 0), 80.);
 
     while !window.is_key_down(Key::Escape) && !window.is_key_down(Key::C) {
         let framestart = Instant::now();
         buffer.clear();
+        if settings.shadows {
+            scene.refresh_shadows();
+        }
 
-        let mut renderer = Renderer::cons(&mut buffer, &mesh, &camera, FOV);
-        renderer.render_refframe(&frame);
-        handle_renderer_input(&window, renderer);
-        handle_mutation_input(&window, &mut mesh, &mut mouse);
-        handle_camera_input(&window, &mut camera);
+        let mut renderer = Renderer::cons(&mut buffer, &scene, &camera, config.fov, settings);
+        if let Some(mesh) = scene.get(MAIN_OBJECT) {
+            renderer.render_refframe(&frame, mesh.transform.rotation);
+        }
+        let culled = handle_renderer_input(&window, renderer, &mut settings, picked.as_ref());
+        handle_pick_input(&window, &scene, &camera, config.fov, &mut picked);
+        handle_mutation_input(&window, &mut scene, &mut mouse);
+        handle_camera_input(&window, &mut camera, &scene);
+        handle_settings_input(&window, &mut settings);
 
-        window.update_with_buffer(buffer.get_pixels(), buffer.width, buffer.height).unwrap();
+        buffer.resolve();
+        window.update_with_buffer(buffer.get_pixels(), buffer.output_width(), buffer.output_height()).unwrap();
         print!("\x1b[7Hframe time: {ftime: >3} ms", ftime = framestart.elapsed().as_millis());
+        print!("\x1b[8H{}\x1b[K", describe_culling(&culled));
+        print!("\x1b[9H{}\x1b[K", describe_pick(&scene, picked.as_ref()));
     }
     print!("\x1b[0m");
 }
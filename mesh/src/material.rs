@@ -0,0 +1,292 @@
+use std::fs::read_to_string;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::geometry::CullMode;
+use crate::math::Vec2f;
+use crate::obj::ObjError;
+use crate::obj::ObjWarning;
+use crate::render_utils::Color;
+use crate::texture::AddressMode;
+use crate::texture::Filter;
+use crate::texture::Texture;
+use crate::Float;
+
+/// anything but `Opaque` is drawn after all opaque geometry, back to front
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum BlendMode {
+    Opaque,
+    Alpha,
+    Additive,
+    Multiply,
+}
+
+/// colors are 0-255 like everything else, `alpha_cutoff` is 0-1
+#[derive(Clone)]
+pub struct Material {
+    pub name: String,
+    /// parsed but unused, the scene's ambient light comes from `Lighting`
+    pub ambient: Color,
+    pub diffuse: Color,
+    pub specular: Color,
+    pub shininess: Float,
+    pub dissolve: Float,
+    /// shared since a texture given on the command line goes on every material
+    pub diffuse_map: Option<Arc<Texture>>,
+    /// loaded as plain data, nothing shades with it yet
+    pub bump_map: Option<Arc<Texture>>,
+    /// overrides the renderer's filter for this material's maps when set
+    pub filter: Option<Filter>,
+    pub blend: BlendMode,
+    pub alpha_cutoff: Option<Float>,
+    /// overrides the mesh's cull mode when set, like for double sided foliage
+    pub cull: Option<CullMode>,
+}
+
+impl Material {
+    pub fn cons(name: &str) -> Material {
+        Material {
+            name: name.to_string(),
+            ambient: Color::cons(0, 0, 0),
+            diffuse: Color::cons(255, 255, 255),
+            specular: Color::cons(0, 0, 0),
+            shininess: 1.,
+            dissolve: 1.,
+            diffuse_map: None,
+            bump_map: None,
+            filter: None,
+            blend: BlendMode::Opaque,
+            alpha_cutoff: None,
+            cull: None,
+        }
+    }
+
+    /// blends below full dissolve, alpha tests opaque materials with see-through maps
+    pub fn infer_transparency(&mut self) {
+        if self.blend == BlendMode::Opaque && self.dissolve < 1. {
+            self.blend = BlendMode::Alpha;
+        }
+        let translucent = self.diffuse_map.as_ref().is_some_and(|texture| texture.translucent);
+        if self.blend == BlendMode::Opaque && self.alpha_cutoff.is_none() && translucent {
+            self.alpha_cutoff = Some(0.5);
+        }
+    }
+
+    pub fn is_transparent(&self) -> bool {
+        self.blend != BlendMode::Opaque
+    }
+
+    /// texture paths are relative to the library, maps that fail to load only warn
+    pub fn build_library_from_file(path: &str) -> Result<(Vec<Material>, Vec<ObjWarning>), ObjError> {
+        let source = read_to_string(path).map_err(|error| ObjError::Io { path: path.to_string(), error })?;
+        let directory = Path::new(path).parent().unwrap_or(Path::new(""));
+
+        let mut materials: Vec<Material> = Vec::new();
+        let mut warnings = Vec::new();
+        for (idx, line) in source.lines().enumerate() {
+            let line_number = idx + 1;
+            let error = |message: String| ObjError::Parse { path: path.to_string(), line: line_number, message };
+            let warning = |message: String| ObjWarning { path: path.to_string(), line: line_number, message };
+
+            let line = line.split('#').next().unwrap_or_default();
+            let parts: Vec<&str> = line.split_whitespace().collect();
+            if parts.is_empty() {
+                continue;
+            }
+
+            let args = &parts[1..];
+            if parts[0] == "newmtl" {
+                materials.push(Material::cons(&args.join(" ")));
+                continue;
+            }
+            let Some(material) = materials.last_mut()
+            else {
+                return Err(error(format!("'{}' before any 'newmtl'", parts[0])));
+            };
+
+            match parts[0] {
+                "Ka" => material.ambient = parse_color(args).map_err(error)?,
+                "Kd" => material.diffuse = parse_color(args).map_err(error)?,
+                "Ks" => material.specular = parse_color(args).map_err(error)?,
+                "Ns" => material.shininess = parse_float(args).map_err(error)?,
+                "d" => material.dissolve = parse_float(args).map_err(error)?,
+                "Tr" => material.dissolve = 1. - parse_float(args).map_err(error)?,
+                "map_Kd" => match load_map(directory, args, true) {
+                    Ok(texture) => material.diffuse_map = Some(Arc::new(texture)),
+                    Err(message) => warnings.push(warning(message)),
+                },
+                "map_Bump" | "map_bump" | "bump" => match load_map(directory, args, false) {
+                    Ok(texture) => material.bump_map = Some(Arc::new(texture)),
+                    Err(message) => warnings.push(warning(message)),
+                },
+                statement => warnings.push(warning(format!("unsupported statement '{}' ignored", statement))),
+            }
+        }
+
+        materials.iter_mut().for_each(Material::infer_transparency);
+        Ok((materials, warnings))
+    }
+
+    /// the map if there is one, otherwise the vertex colors, with the dissolve folded into alpha
+    #[inline]
+    pub fn sample_diffuse(&self, uv: Vec2f, lod: Float, filter: Filter, vertex_color: Color) -> Color {
+        let color = match &self.diffuse_map {
+            Some(texture) => texture.sample(uv.x, uv.y, lod, filter).modulate(&self.diffuse),
+            None => vertex_color.modulate(&self.diffuse),
+        };
+        color.with_alpha(color.alpha * self.dissolve)
+    }
+
+    /// `ddx` and `ddy` are how far the uvs move one pixel over and one pixel up
+    #[inline]
+    pub fn diffuse_lod(&self, ddx: Vec2f, ddy: Vec2f) -> Float {
+        match &self.diffuse_map {
+            Some(texture) => texture.lod(ddx, ddy),
+            None => 0.,
+        }
+    }
+}
+
+impl Default for Material {
+    fn default() -> Material {
+        Material::cons("default")
+    }
+}
+
+fn parse_float(args: &[&str]) -> Result<Float, String> {
+    let value = args.first().ok_or("expected a value")?;
+    value.parse().map_err(|_| format!("invalid number '{}'", value))
+}
+
+fn parse_color(args: &[&str]) -> Result<Color, String> {
+    if args.first() == Some(&"spectral") || args.first() == Some(&"xyz") {
+        return Err(format!("'{}' colors are not supported", args[0]));
+    }
+    let red = parse_float(args)?;
+    // a single value means a grey
+    let (green, blue) = match args.len() {
+        1 => (red, red),
+        _ => (parse_float(&args[1..])?, parse_float(&args[2..])?),
+    };
+    Ok(Color::cons(red * 255., green * 255., blue * 255.))
+}
+
+/// the file name comes after any options, `-clamp on` is the only one that's used
+/// `color` maps are decoded from srgb, anything else is loaded as plain data
+fn load_map(directory: &Path, args: &[&str], color: bool) -> Result<Texture, String> {
+    let file = args.last().ok_or("expected a texture path")?;
+    let path = directory.join(file);
+    let loaded = if color {
+        Texture::build_from_file(&path.to_string_lossy())
+    }
+    else {
+        Texture::build_data_from_file(&path.to_string_lossy())
+    };
+    let mut texture = loaded.map_err(|error| format!("failed to load texture '{}': {}", path.display(), error))?;
+
+    let options = &args[..args.len() - 1];
+    if let Some(idx) = options.iter().position(|option| *option == "-clamp") {
+        texture.address = match options.get(idx + 1) {
+            Some(&"on") => AddressMode::ClampToEdge,
+            Some(&"off") => AddressMode::Repeat,
+            _ => return Err("'-clamp' expects 'on' or 'off'".to_string()),
+        };
+    }
+
+    Ok(texture)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+    use image::RgbaImage;
+    use std::env::temp_dir;
+    use std::fs::create_dir_all;
+    use std::fs::write;
+
+    /// writes `source` as `name.mtl` in its own directory next to a 2x2 `maps/checker.png`
+    fn library(name: &str, source: &str) -> Result<(Vec<Material>, Vec<ObjWarning>), ObjError> {
+        let directory = temp_dir().join(format!("mesh_mtl_{}", name));
+        create_dir_all(directory.join("maps")).unwrap();
+        RgbaImage::from_pixel(2, 2, Rgba([255, 0, 0, 128])).save(directory.join("maps/checker.png")).unwrap();
+        let path = directory.join(format!("{}.mtl", name));
+        write(&path, source).unwrap();
+        Material::build_library_from_file(&path.to_string_lossy())
+    }
+
+    fn close(lhs: Float, rhs: Float) -> bool {
+        (lhs - rhs).abs() < 1e-3
+    }
+
+    #[test]
+    fn parses_colors_and_scalars() {
+        let source =
+            "newmtl shiny\nKa 0.1 0.2 0.3\nKd 1 0.5 0\nKs 0.2\nNs 64\nd 0.25\n\nnewmtl glass # comment\nTr 0.75\n";
+        let (materials, warnings) = library("scalars", source).unwrap();
+        assert!(warnings.is_empty(), "{:?}", warnings);
+        assert_eq!(materials.len(), 2);
+
+        let shiny = &materials[0];
+        assert_eq!(shiny.name, "shiny");
+        assert_eq!(shiny.ambient, Color::cons(25.5, 51., 76.5));
+        assert_eq!(shiny.diffuse, Color::cons(255., 127.5, 0.));
+        assert_eq!(shiny.specular, Color::cons(51., 51., 51.));
+        assert!(close(shiny.shininess, 64.) && close(shiny.dissolve, 0.25));
+        assert_eq!(shiny.blend, BlendMode::Alpha);
+
+        let glass = &materials[1];
+        assert_eq!(glass.name, "glass");
+        assert!(close(glass.dissolve, 0.25));
+        assert!(glass.diffuse_map.is_none() && glass.bump_map.is_none());
+        assert_eq!(glass.ambient, Color::cons(0, 0, 0));
+    }
+
+    #[test]
+    fn map_paths_are_relative_to_the_library() {
+        let source = "newmtl skin\nmap_Kd -clamp on maps/checker.png\nmap_Bump -bm 0.5 -clamp off maps/checker.png\n";
+        let (materials, warnings) = library("map", source).unwrap();
+        assert!(warnings.is_empty(), "{:?}", warnings);
+        let texture = materials[0].diffuse_map.as_ref().unwrap();
+        assert_eq!((texture.width, texture.height), (2, 2));
+        assert_eq!(texture.address, AddressMode::ClampToEdge);
+        let bump = materials[0].bump_map.as_ref().unwrap();
+        assert_eq!((bump.width, bump.height), (2, 2));
+        assert_eq!(bump.address, AddressMode::Repeat);
+        // a half transparent map on an otherwise opaque material gets alpha tested
+        assert_eq!(materials[0].blend, BlendMode::Opaque);
+        assert_eq!(materials[0].alpha_cutoff, Some(0.5));
+    }
+
+    #[test]
+    fn unknown_statements_and_missing_maps_only_warn() {
+        let source = "newmtl odd\nKa 1 1 1\nillum 2\nmap_Kd maps/missing.png\nbump maps/gone.png\nKd 0 1 0\n";
+        let (materials, warnings) = library("warnings", source).unwrap();
+        let lines: Vec<usize> = warnings.iter().map(|warning| warning.line).collect();
+        assert_eq!(lines, [3, 4, 5]);
+        assert!(warnings[0].message.contains("'illum'"), "{}", warnings[0].message);
+        assert!(warnings[1].message.contains("missing.png"), "{}", warnings[1].message);
+        assert!(warnings[2].message.contains("gone.png"), "{}", warnings[2].message);
+        assert!(materials[0].diffuse_map.is_none() && materials[0].bump_map.is_none());
+        assert_eq!(materials[0].ambient, Color::cons(255, 255, 255));
+        assert_eq!(materials[0].diffuse, Color::cons(0, 255, 0));
+    }
+
+    #[test]
+    fn bad_statements_are_errors() {
+        for (source, line) in [("Kd 1 1 1\n", 1), ("newmtl a\nNs shiny\n", 2), ("newmtl a\n\nKd xyz 1 1 1\n", 3)] {
+            match library("errors", source) {
+                Err(ObjError::Parse { line: found, .. }) => assert_eq!(found, line, "{:?}", source),
+                Err(other) => panic!("expected a parse error, got {}", other),
+                Ok(_) => panic!("{:?} parsed", source),
+            }
+        }
+    }
+
+    #[test]
+    fn missing_library_is_an_io_error() {
+        let path = temp_dir().join("mesh_mtl_nowhere/missing.mtl");
+        let result = Material::build_library_from_file(&path.to_string_lossy());
+        assert!(matches!(result, Err(ObjError::Io { .. })));
+    }
+}
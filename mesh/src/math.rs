@@ -125,6 +125,17 @@ impl Vec2f {
     {
         Vec2f { x: x.floatify(), y: y.floatify() }
     }
+
+    pub fn lerp(&self, other: &Vec2f, t: Float) -> Vec2f {
+        Vec2f::cons(self.x + (other.x - self.x) * t, self.y + (other.y - self.y) * t)
+    }
+}
+
+impl Sub for Vec2f {
+    type Output = Vec2f;
+    fn sub(self, other: Vec2f) -> Self::Output {
+        Vec2f::cons(self.x - other.x, self.y - other.y)
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -246,11 +257,15 @@ impl Vec3f {
     pub fn cross(&self, other: &Self) -> Self {
         Vec3f::cons(
             self.y * other.z - self.z * other.y,
-            self.x * other.z - self.z * other.x,
+            self.z * other.x - self.x * other.z,
             self.x * other.y - self.y * other.x,
         )
     }
 
+    pub fn lerp(&self, other: &Vec3f, t: Float) -> Vec3f {
+        *self + (*other - *self) * t
+    }
+
     pub fn clamp_xy(&mut self, min_x: Float, max_x: Float, min_y: Float, max_y: Float) {
         self.x = self.x.clamp(min_x, max_x);
         self.y = self.y.clamp(min_y, max_y);
@@ -323,3 +338,381 @@ impl Neg for Vec3f {
         Vec3f::cons(-self.x, -self.y, -self.z)
     }
 }
+
+#[derive(Debug, Clone, Copy)]
+pub struct Quat {
+    pub w: Float,
+    pub x: Float,
+    pub y: Float,
+    pub z: Float,
+}
+
+impl Quat {
+    pub fn cons(w: Float, x: Float, y: Float, z: Float) -> Quat {
+        Quat { w, x, y, z }
+    }
+
+    pub fn identity() -> Quat {
+        Quat::cons(1., 0., 0., 0.)
+    }
+
+    pub fn from_axis_angle(axis: Vec3f, angle: Float) -> Quat {
+        let axis = axis.get_normalized();
+        let (sin, cos) = (angle / 2.).sin_cos();
+        Quat::cons(cos, axis.x * sin, axis.y * sin, axis.z * sin)
+    }
+
+    /// same convention as `Vec3f::rot_xyz`, rotates about x then y then z
+    pub fn from_euler(angles: Vec3f) -> Quat {
+        let qx = Quat::from_axis_angle(Vec3f::cons(1, 0, 0), angles.x);
+        let qy = Quat::from_axis_angle(Vec3f::cons(0, 1, 0), angles.y);
+        let qz = Quat::from_axis_angle(Vec3f::cons(0, 0, 1), angles.z);
+        qz * qy * qx
+    }
+
+    pub fn conjugate(&self) -> Quat {
+        Quat::cons(self.w, -self.x, -self.y, -self.z)
+    }
+
+    pub fn normalize(&mut self) {
+        let length = (self.w * self.w + self.x * self.x + self.y * self.y + self.z * self.z).sqrt();
+        self.w /= length;
+        self.x /= length;
+        self.y /= length;
+        self.z /= length;
+    }
+
+    pub fn rotate(&self, vec: Vec3f) -> Vec3f {
+        self.to_mat3() * vec
+    }
+
+    pub fn to_mat3(self) -> Mat3 {
+        let Quat { w, x, y, z } = self;
+        Mat3::cons([
+            [1. - 2. * (y * y + z * z), 2. * (x * y - w * z), 2. * (x * z + w * y)],
+            [2. * (x * y + w * z), 1. - 2. * (x * x + z * z), 2. * (y * z - w * x)],
+            [2. * (x * z - w * y), 2. * (y * z + w * x), 1. - 2. * (x * x + y * y)],
+        ])
+    }
+}
+
+impl Mul for Quat {
+    type Output = Quat;
+    fn mul(self, other: Quat) -> Self::Output {
+        Quat::cons(
+            self.w * other.w - self.x * other.x - self.y * other.y - self.z * other.z,
+            self.w * other.x + self.x * other.w + self.y * other.z - self.z * other.y,
+            self.w * other.y - self.x * other.z + self.y * other.w + self.z * other.x,
+            self.w * other.z + self.x * other.y - self.y * other.x + self.z * other.w,
+        )
+    }
+}
+
+/// row major, vectors are columns so `a * b * vec` applies b first
+#[derive(Debug, Clone, Copy)]
+pub struct Mat3 {
+    pub m: [[Float; 3]; 3],
+}
+
+impl Mat3 {
+    pub fn cons(m: [[Float; 3]; 3]) -> Mat3 {
+        Mat3 { m }
+    }
+
+    pub fn identity() -> Mat3 {
+        Mat3::cons([[1., 0., 0.], [0., 1., 0.], [0., 0., 1.]])
+    }
+
+    pub fn scaling(scale: Vec3f) -> Mat3 {
+        Mat3::cons([[scale.x, 0., 0.], [0., scale.y, 0.], [0., 0., scale.z]])
+    }
+
+    pub fn transpose(&self) -> Mat3 {
+        let m = self.m;
+        Mat3::cons([[m[0][0], m[1][0], m[2][0]], [m[0][1], m[1][1], m[2][1]], [m[0][2], m[1][2], m[2][2]]])
+    }
+
+    pub fn determinant(&self) -> Float {
+        let m = self.m;
+        m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1]) - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+            + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+    }
+
+    pub fn inverse(&self) -> Option<Mat3> {
+        let det = self.determinant();
+        if det.abs() < Float::EPSILON {
+            return None;
+        }
+
+        let m = self.m;
+        let inv_det = 1. / det;
+        Some(Mat3::cons([
+            [
+                (m[1][1] * m[2][2] - m[1][2] * m[2][1]) * inv_det,
+                (m[0][2] * m[2][1] - m[0][1] * m[2][2]) * inv_det,
+                (m[0][1] * m[1][2] - m[0][2] * m[1][1]) * inv_det,
+            ],
+            [
+                (m[1][2] * m[2][0] - m[1][0] * m[2][2]) * inv_det,
+                (m[0][0] * m[2][2] - m[0][2] * m[2][0]) * inv_det,
+                (m[0][2] * m[1][0] - m[0][0] * m[1][2]) * inv_det,
+            ],
+            [
+                (m[1][0] * m[2][1] - m[1][1] * m[2][0]) * inv_det,
+                (m[0][1] * m[2][0] - m[0][0] * m[2][1]) * inv_det,
+                (m[0][0] * m[1][1] - m[0][1] * m[1][0]) * inv_det,
+            ],
+        ]))
+    }
+
+    /// inverse transpose, keeps normals perpendicular under non-uniform scale
+    pub fn normal_matrix(&self) -> Mat3 {
+        self.inverse().unwrap_or(*self).transpose()
+    }
+}
+
+impl Mul for Mat3 {
+    type Output = Mat3;
+    fn mul(self, other: Mat3) -> Self::Output {
+        let mut out = [[0.; 3]; 3];
+        for (row, out_row) in out.iter_mut().enumerate() {
+            for (col, value) in out_row.iter_mut().enumerate() {
+                *value = (0..3).map(|idx| self.m[row][idx] * other.m[idx][col]).sum();
+            }
+        }
+        Mat3::cons(out)
+    }
+}
+
+impl Mul<Vec3f> for Mat3 {
+    type Output = Vec3f;
+    fn mul(self, vec: Vec3f) -> Self::Output {
+        let m = self.m;
+        Vec3f::cons(
+            m[0][0] * vec.x + m[0][1] * vec.y + m[0][2] * vec.z,
+            m[1][0] * vec.x + m[1][1] * vec.y + m[1][2] * vec.z,
+            m[2][0] * vec.x + m[2][1] * vec.y + m[2][2] * vec.z,
+        )
+    }
+}
+
+/// row major, vectors are columns so `a * b * vec` applies b first
+#[derive(Debug, Clone, Copy)]
+pub struct Mat4 {
+    pub m: [[Float; 4]; 4],
+}
+
+impl Mat4 {
+    pub fn cons(m: [[Float; 4]; 4]) -> Mat4 {
+        Mat4 { m }
+    }
+
+    pub fn identity() -> Mat4 {
+        Mat4::cons([[1., 0., 0., 0.], [0., 1., 0., 0.], [0., 0., 1., 0.], [0., 0., 0., 1.]])
+    }
+
+    pub fn translation(offset: Vec3f) -> Mat4 {
+        Mat4::cons([[1., 0., 0., offset.x], [0., 1., 0., offset.y], [0., 0., 1., offset.z], [0., 0., 0., 1.]])
+    }
+
+    pub fn from_mat3(mat: Mat3) -> Mat4 {
+        let m = mat.m;
+        Mat4::cons([
+            [m[0][0], m[0][1], m[0][2], 0.],
+            [m[1][0], m[1][1], m[1][2], 0.],
+            [m[2][0], m[2][1], m[2][2], 0.],
+            [0., 0., 0., 1.],
+        ])
+    }
+
+    pub fn to_mat3(self) -> Mat3 {
+        let m = self.m;
+        Mat3::cons([[m[0][0], m[0][1], m[0][2]], [m[1][0], m[1][1], m[1][2]], [m[2][0], m[2][1], m[2][2]]])
+    }
+
+    pub fn get_translation(&self) -> Vec3f {
+        Vec3f::cons(self.m[0][3], self.m[1][3], self.m[2][3])
+    }
+
+    /// only right for affine matrices, which is all the model and view ones
+    pub fn inverse_affine(&self) -> Option<Mat4> {
+        let linear = self.to_mat3().inverse()?;
+        Some(Mat4::translation(-(linear * self.get_translation())) * Mat4::from_mat3(linear))
+    }
+
+    /// treats the matrix as affine and ignores the bottom row
+    #[inline]
+    pub fn transform_point(&self, point: &Vec3f) -> Vec3f {
+        let m = self.m;
+        Vec3f::cons(
+            m[0][0] * point.x + m[0][1] * point.y + m[0][2] * point.z + m[0][3],
+            m[1][0] * point.x + m[1][1] * point.y + m[1][2] * point.z + m[1][3],
+            m[2][0] * point.x + m[2][1] * point.y + m[2][2] * point.z + m[2][3],
+        )
+    }
+
+    #[inline]
+    pub fn transform_vector(&self, vec: &Vec3f) -> Vec3f {
+        self.to_mat3() * *vec
+    }
+
+    /// x and y get divided by w, z is handed back as is to keep linear view depth
+    #[inline]
+    pub fn project(&self, point: &Vec3f) -> Vec3f {
+        let m = self.m;
+        let w = m[3][0] * point.x + m[3][1] * point.y + m[3][2] * point.z + m[3][3];
+        let clip = self.transform_point(point);
+        Vec3f::cons(clip.x / w, clip.y / w, clip.z)
+    }
+}
+
+impl Mul for Mat4 {
+    type Output = Mat4;
+    fn mul(self, other: Mat4) -> Self::Output {
+        let mut out = [[0.; 4]; 4];
+        for (row, out_row) in out.iter_mut().enumerate() {
+            for (col, value) in out_row.iter_mut().enumerate() {
+                *value = (0..4).map(|idx| self.m[row][idx] * other.m[idx][col]).sum();
+            }
+        }
+        Mat4::cons(out)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Transform {
+    pub position: Vec3f,
+    pub rotation: Quat,
+    pub scale: Vec3f,
+}
+
+impl Transform {
+    pub fn cons(position: Vec3f) -> Transform {
+        Transform { position, rotation: Quat::identity(), scale: Vec3f::cons(1, 1, 1) }
+    }
+
+    /// scale, then rotate, then translate
+    pub fn matrix(&self) -> Mat4 {
+        let linear = self.rotation.to_mat3() * Mat3::scaling(self.scale);
+        Mat4::translation(self.position) * Mat4::from_mat3(linear)
+    }
+
+    pub fn child_matrix(&self, parent: &Mat4) -> Mat4 {
+        *parent * self.matrix()
+    }
+
+    /// rotates about an axis in the object's own frame
+    pub fn rotate_local(&mut self, axis: Vec3f, angle: Float) {
+        self.rotation = self.rotation * Quat::from_axis_angle(axis, angle);
+        self.rotation.normalize();
+    }
+
+    /// rotates about an axis fixed in the parent frame
+    pub fn rotate_world(&mut self, axis: Vec3f, angle: Float) {
+        self.rotation = Quat::from_axis_angle(axis, angle) * self.rotation;
+        self.rotation.normalize();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Lcg;
+    use crate::PI;
+
+    fn close(lhs: Vec3f, rhs: Vec3f) -> bool {
+        let gap = lhs - rhs;
+        gap.inner_prod(&gap) < 1e-8
+    }
+
+    fn is_identity(mat: &Mat4) -> bool {
+        let identity = Mat4::identity();
+        (0..4).all(|row| (0..4).all(|col| (mat.m[row][col] - identity.m[row][col]).abs() < 1e-4))
+    }
+
+    /// rotation, non-uniform scale and translation, always invertible
+    fn affine(rng: &mut Lcg) -> Mat4 {
+        let transform = Transform {
+            position: rng.vec(-5., 5.),
+            rotation: Quat::from_axis_angle(rng.vec(-1., 1.), rng.range(-PI, PI)),
+            scale: rng.vec(0.5, 3.),
+        };
+        transform.matrix()
+    }
+
+    #[test]
+    fn cross_follows_the_right_hand_rule() {
+        let (x, y, z) = (Vec3f::cons(1, 0, 0), Vec3f::cons(0, 1, 0), Vec3f::cons(0, 0, 1));
+        assert!(close(x.cross(&y), z));
+        assert!(close(y.cross(&z), x));
+        assert!(close(z.cross(&x), y));
+        assert!(close(x.cross(&z), -y));
+
+        let (lhs, rhs) = (Vec3f::cons(2, 3, 4), Vec3f::cons(5, 6, 7));
+        assert!(close(lhs.cross(&rhs), Vec3f::cons(-3, 6, -3)));
+    }
+
+    #[test]
+    fn quat_rotation_matches_its_matrix() {
+        let mut rng = Lcg(3);
+        for _ in 0..50 {
+            let quat = Quat::from_axis_angle(rng.vec(-1., 1.), rng.range(-PI, PI));
+            let vec = rng.vec(-4., 4.);
+            let sandwich = quat * Quat::cons(0., vec.x, vec.y, vec.z) * quat.conjugate();
+            assert!(close(quat.rotate(vec), Vec3f::cons(sandwich.x, sandwich.y, sandwich.z)));
+        }
+
+        let mut rng = Lcg(5);
+        for _ in 0..50 {
+            let (angles, vec) = (rng.vec(-PI, PI), rng.vec(-4., 4.));
+            let mut rotated = vec;
+            rotated.rot_xyz(angles);
+            assert!(close(Quat::from_euler(angles).rotate(vec), rotated));
+
+            let mut about_x = vec;
+            about_x.rot_x(angles.x);
+            assert!(close(Quat::from_axis_angle(Vec3f::cons(1, 0, 0), angles.x).to_mat3() * vec, about_x));
+        }
+    }
+
+    #[test]
+    fn inverse_undoes_the_matrix() {
+        let mut rng = Lcg(9);
+        for _ in 0..50 {
+            let mat = affine(&mut rng);
+            let inverse = mat.inverse_affine().unwrap();
+            assert!(is_identity(&(inverse * mat)));
+            assert!(is_identity(&(mat * inverse)));
+        }
+        assert!(Mat4::from_mat3(Mat3::scaling(Vec3f::cons(1, 0, 1))).inverse_affine().is_none());
+    }
+
+    #[test]
+    fn transform_scales_then_rotates_then_translates() {
+        let mut transform = Transform::cons(Vec3f::cons(10, 0, 0));
+        transform.scale = Vec3f::cons(2, 1, 1);
+        transform.rotate_local(Vec3f::cons(0, 0, 1), PI / 2.);
+        let moved = transform.matrix().transform_point(&Vec3f::cons(1, 0, 0));
+        assert!(close(moved, Vec3f::cons(10, 2, 0)), "{:?}", moved);
+
+        let scale = Mat4::from_mat3(Mat3::scaling(Vec3f::cons(3, 3, 3)));
+        let parent = Transform::cons(Vec3f::cons(0, 0, 5)).matrix() * scale;
+        let child = transform.child_matrix(&parent).transform_point(&Vec3f::cons(1, 0, 0));
+        assert!(close(child, parent.transform_point(&moved)), "{:?}", child);
+    }
+
+    #[test]
+    fn local_and_world_rotations_compose_on_opposite_sides() {
+        let (x, z) = (Vec3f::cons(1, 0, 0), Vec3f::cons(0, 0, 1));
+        let mut local = Transform::cons(Vec3f::cons(0, 0, 0));
+        local.rotate_local(z, PI / 2.);
+        local.rotate_local(x, PI / 2.);
+        // the second turn is about the object's own x, which the first turned onto world y
+        assert!(close(local.matrix().transform_point(&Vec3f::cons(0, 1, 0)), z));
+
+        let mut world = Transform::cons(Vec3f::cons(0, 0, 0));
+        world.rotate_world(z, PI / 2.);
+        world.rotate_world(x, PI / 2.);
+        assert!(close(world.matrix().transform_point(&Vec3f::cons(0, 1, 0)), -x));
+    }
+}
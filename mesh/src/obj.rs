@@ -0,0 +1,424 @@
+use std::error::Error;
+use std::fmt;
+use std::fs::read_to_string;
+use std::io;
+use std::str::FromStr;
+
+use crate::math::Vec2f;
+use crate::math::Vec3f;
+use crate::render_utils::Color;
+use crate::Float;
+
+#[derive(Debug)]
+pub enum ObjError {
+    Io { path: String, error: io::Error },
+    Parse { path: String, line: usize, message: String },
+    Texture { path: String, message: String },
+}
+
+impl fmt::Display for ObjError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ObjError::Io { path, error } => write!(f, "{}: {}", path, error),
+            ObjError::Parse { path, line, message } => write!(f, "{}:{}: {}", path, line, message),
+            ObjError::Texture { path, message } => write!(f, "{}: failed to load texture: {}", path, message),
+        }
+    }
+}
+
+impl Error for ObjError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ObjError::Io { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
+
+/// something the loader skipped over instead of failing on
+#[derive(Debug)]
+pub struct ObjWarning {
+    pub path: String,
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ObjWarning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.path, self.line, self.message)
+    }
+}
+
+/// 0-based, negative indices are resolved while parsing since they're relative
+#[derive(Debug, Clone, Copy)]
+pub struct ObjIndex {
+    pub pos: usize,
+    pub tex: Option<usize>,
+    pub normal: Option<usize>,
+}
+
+#[derive(Debug)]
+pub struct ObjFace {
+    pub verts: Vec<ObjIndex>,
+    /// none until the file has an `s` statement, `s off` is 0
+    pub smoothing: Option<u32>,
+    pub material: Option<usize>,
+}
+
+#[derive(Debug)]
+pub struct ObjGroup {
+    pub object: String,
+    pub name: String,
+    pub faces: Vec<ObjFace>,
+}
+
+#[derive(Debug, Default)]
+pub struct ObjData {
+    pub positions: Vec<Vec3f>,
+    pub colors: Vec<Option<Color>>,
+    pub texcoords: Vec<Vec2f>,
+    pub normals: Vec<Vec3f>,
+    pub groups: Vec<ObjGroup>,
+    pub material_libs: Vec<(usize, String)>,
+    pub material_names: Vec<String>,
+    pub warnings: Vec<ObjWarning>,
+}
+
+impl ObjData {
+    pub fn load(path: &str) -> Result<ObjData, ObjError> {
+        let source = read_to_string(path).map_err(|error| ObjError::Io { path: path.to_string(), error })?;
+        ObjData::parse(&source, path)
+    }
+
+    pub fn parse(source: &str, path: &str) -> Result<ObjData, ObjError> {
+        let mut parser = Parser {
+            path,
+            line: 0,
+            data: ObjData::default(),
+            object: String::new(),
+            smoothing: None,
+            material: None,
+        };
+        parser.start_group("default");
+
+        let mut pending = String::new();
+        let mut pending_line = 0;
+        for (idx, line) in source.lines().enumerate() {
+            if pending.is_empty() {
+                pending_line = idx + 1;
+            }
+            // a trailing backslash joins the next physical line onto this one
+            if let Some(stripped) = line.strip_suffix('\\') {
+                pending.push_str(stripped);
+                pending.push(' ');
+                continue;
+            }
+            pending.push_str(line);
+
+            parser.line = pending_line;
+            parser.statement(&pending)?;
+            pending.clear();
+        }
+        if !pending.is_empty() {
+            parser.line = pending_line;
+            parser.statement(&pending)?;
+        }
+
+        let mut data = parser.data;
+        data.groups.retain(|group| !group.faces.is_empty());
+        Ok(data)
+    }
+
+    pub fn face_count(&self) -> usize {
+        self.groups.iter().map(|group| group.faces.len()).sum()
+    }
+}
+
+struct Parser<'d> {
+    path: &'d str,
+    line: usize,
+    data: ObjData,
+    object: String,
+    smoothing: Option<u32>,
+    material: Option<usize>,
+}
+
+impl Parser<'_> {
+    fn statement(&mut self, line: &str) -> Result<(), ObjError> {
+        let line = line.split('#').next().unwrap_or_default();
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        if parts.is_empty() {
+            return Ok(());
+        }
+
+        let args = &parts[1..];
+        match parts[0] {
+            "v" => {
+                self.expect_args(args, 3, "v")?;
+                let pos = Vec3f::cons(self.float(args[0])?, self.float(args[1])?, self.float(args[2])?);
+                // the common `v x y z r g b` extension, a lone 4th value is w and is ignored
+                let color = match args.len() {
+                    6 | 7 => {
+                        let (r, g, b) = (self.float(args[3])?, self.float(args[4])?, self.float(args[5])?);
+                        Some(Color::cons(r * 255., g * 255., b * 255.))
+                    }
+                    _ => None,
+                };
+                self.data.positions.push(pos);
+                self.data.colors.push(color);
+            }
+            "vt" => {
+                self.expect_args(args, 1, "vt")?;
+                let u = self.float(args[0])?;
+                let v = match args.get(1) {
+                    Some(v) => self.float(v)?,
+                    None => 0.,
+                };
+                self.data.texcoords.push(Vec2f::cons(u, v));
+            }
+            "vn" => {
+                self.expect_args(args, 3, "vn")?;
+                let normal = Vec3f::cons(self.float(args[0])?, self.float(args[1])?, self.float(args[2])?);
+                self.data.normals.push(normal);
+            }
+            "f" => {
+                self.expect_args(args, 3, "f")?;
+                let verts = args.iter().map(|arg| self.face_index(arg)).collect::<Result<Vec<_>, _>>()?;
+                let (smoothing, material) = (self.smoothing, self.material);
+                self.current_group().faces.push(ObjFace { verts, smoothing, material });
+            }
+            "o" => {
+                self.object = args.join(" ");
+                self.start_group("default");
+            }
+            "g" => {
+                let name = if args.is_empty() {
+                    "default".to_string()
+                }
+                else {
+                    args.join(" ")
+                };
+                self.start_group(&name);
+            }
+            "s" => {
+                self.expect_args(args, 1, "s")?;
+                self.smoothing = match args[0] {
+                    "off" => Some(0),
+                    value => Some(self.number(value)?),
+                };
+            }
+            "mtllib" => {
+                self.expect_args(args, 1, "mtllib")?;
+                // blender file names often have spaces, so the library gets the rest of the line
+                self.data.material_libs.push((self.line, args.join(" ")));
+            }
+            "usemtl" => {
+                self.expect_args(args, 1, "usemtl")?;
+                let name = args.join(" ");
+                let idx = match self.data.material_names.iter().position(|existing| *existing == name) {
+                    Some(idx) => idx,
+                    None => {
+                        self.data.material_names.push(name);
+                        self.data.material_names.len() - 1
+                    }
+                };
+                self.material = Some(idx);
+            }
+            statement => {
+                let message = format!("unsupported statement '{}' ignored", statement);
+                self.data.warnings.push(ObjWarning { path: self.path.to_string(), line: self.line, message });
+            }
+        }
+
+        Ok(())
+    }
+
+    fn start_group(&mut self, name: &str) {
+        let object = self.object.clone();
+        self.data.groups.push(ObjGroup { object, name: name.to_string(), faces: Vec::new() });
+    }
+
+    fn current_group(&mut self) -> &mut ObjGroup {
+        self.data.groups.last_mut().expect("parser always has a group")
+    }
+
+    fn face_index(&self, arg: &str) -> Result<ObjIndex, ObjError> {
+        let mut indices = arg.split('/');
+        let pos = indices.next().unwrap_or_default();
+        let pos = self.resolve(pos, self.data.positions.len(), "vertex")?;
+        let tex = match indices.next() {
+            Some(tex) if !tex.is_empty() => Some(self.resolve(tex, self.data.texcoords.len(), "texture coordinate")?),
+            _ => None,
+        };
+        let normal = match indices.next() {
+            Some(normal) if !normal.is_empty() => Some(self.resolve(normal, self.data.normals.len(), "normal")?),
+            _ => None,
+        };
+        if indices.next().is_some() {
+            return Err(self.error(format!("malformed face index '{}'", arg)));
+        }
+
+        Ok(ObjIndex { pos, tex, normal })
+    }
+
+    fn resolve(&self, index: &str, count: usize, kind: &str) -> Result<usize, ObjError> {
+        let index: i64 = self.number(index)?;
+        let resolved = match index {
+            0 => return Err(self.error(format!("{} index 0 is invalid, indices start at 1", kind))),
+            positive if positive > 0 => positive - 1,
+            negative => count as i64 + negative,
+        };
+        if resolved < 0 || resolved >= count as i64 {
+            return Err(self.error(format!("{} index {} out of range, {} defined so far", kind, index, count)));
+        }
+
+        Ok(resolved as usize)
+    }
+
+    fn expect_args(&self, args: &[&str], count: usize, statement: &str) -> Result<(), ObjError> {
+        if args.len() < count {
+            return Err(self.error(format!("'{}' expects at least {} values, got {}", statement, count, args.len())));
+        }
+        Ok(())
+    }
+
+    fn number<T>(&self, value: &str) -> Result<T, ObjError>
+    where
+        T: FromStr,
+    {
+        value.parse().map_err(|_| self.error(format!("invalid number '{}'", value)))
+    }
+
+    fn float(&self, value: &str) -> Result<Float, ObjError> {
+        self.number(value)
+    }
+
+    fn error(&self, message: String) -> ObjError {
+        ObjError::Parse { path: self.path.to_string(), line: self.line, message }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(source: &str) -> ObjData {
+        ObjData::parse(source, "test.obj").unwrap()
+    }
+
+    /// the line and message of a parse error, panics on anything else
+    fn parse_error(source: &str) -> (usize, String) {
+        match ObjData::parse(source, "test.obj") {
+            Err(ObjError::Parse { path, line, message }) => {
+                assert_eq!(path, "test.obj");
+                (line, message)
+            }
+            Err(other) => panic!("expected a parse error, got {}", other),
+            Ok(_) => panic!("expected a parse error"),
+        }
+    }
+
+    fn positions(face: &ObjFace) -> Vec<usize> {
+        face.verts.iter().map(|vert| vert.pos).collect()
+    }
+
+    #[test]
+    fn negative_indices_count_back_from_the_face() {
+        let data = parse(
+            "v 0 0 0\nv 1 0 0\nv 0 1 0\nvt 0 0\nvt 1 0\nvt 0 1\nvn 0 0 1\nf -3/-3/-1 -2/-2/-1 -1/-1/-1\n\
+             v 1 1 0\nf -4 -2 -1\n",
+        );
+        let faces = &data.groups[0].faces;
+        assert_eq!(positions(&faces[0]), [0, 1, 2]);
+        assert_eq!(faces[0].verts.iter().map(|vert| vert.tex).collect::<Vec<_>>(), [Some(0), Some(1), Some(2)]);
+        assert!(faces[0].verts.iter().all(|vert| vert.normal == Some(0)));
+        assert_eq!(positions(&faces[1]), [0, 2, 3]);
+    }
+
+    #[test]
+    fn face_index_forms() {
+        let data = parse("v 0 0 0\nv 1 0 0\nv 0 1 0\nvt 0 0\nvn 0 0 1\nf 1//1 2//1 3//1\nf 1/1 2/1 3/1\n");
+        let faces = &data.groups[0].faces;
+        assert!(faces[0].verts.iter().all(|vert| vert.tex.is_none() && vert.normal == Some(0)));
+        assert!(faces[1].verts.iter().all(|vert| vert.tex == Some(0) && vert.normal.is_none()));
+    }
+
+    #[test]
+    fn errors_report_the_line() {
+        assert_eq!(parse_error("v 0 0 0\n\nv 1 x 0\n"), (3, "invalid number 'x'".to_string()));
+        assert_eq!(parse_error("v 0 0\n"), (1, "'v' expects at least 3 values, got 2".to_string()));
+
+        let (line, message) = parse_error("v 0 0 0\nv 1 0 0\nf 1 2 3\n");
+        assert_eq!(line, 3);
+        assert_eq!(message, "vertex index 3 out of range, 2 defined so far");
+        let (line, message) = parse_error("v 0 0 0\nf 1 -2 1\n");
+        assert_eq!((line, message.as_str()), (2, "vertex index -2 out of range, 1 defined so far"));
+        let (line, message) = parse_error("v 0 0 0\nf 0 1 1\n");
+        assert_eq!((line, message.as_str()), (2, "vertex index 0 is invalid, indices start at 1"));
+        let (line, message) = parse_error("v 0 0 0\nvt 0 0\nvn 0 0 1\nf 1/1/1/1 1 1\n");
+        assert_eq!((line, message.as_str()), (4, "malformed face index '1/1/1/1'"));
+    }
+
+    #[test]
+    fn error_display_is_file_and_line() {
+        let error = ObjData::parse("v 0 0 0\nvn 1 2\n", "models/thing.obj").unwrap_err();
+        assert_eq!(error.to_string(), "models/thing.obj:2: 'vn' expects at least 3 values, got 2");
+    }
+
+    #[test]
+    fn continuations_join_lines_and_keep_the_first_line_number() {
+        let data = parse("v 0 0 0\nv 1 \\\n0 0\nv 0 1 0\nf 1 2 \\\n 3\n");
+        assert_eq!(data.positions.len(), 3);
+        assert_eq!(data.positions[1].x, 1.);
+        assert_eq!(positions(&data.groups[0].faces[0]), [0, 1, 2]);
+
+        let (line, _) = parse_error("v 0 0 0\nv 1 \\\n0 \\\nx\n");
+        assert_eq!(line, 2);
+        let (line, _) = parse_error("v 0 0 0\nv 1 \\\n0 0\nv y 0 0\n");
+        assert_eq!(line, 4);
+    }
+
+    #[test]
+    fn objects_groups_and_smoothing() {
+        let data = parse(
+            "v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3\n\
+             o gun\ng barrel tip\ns 1\nf 1 2 3\ns off\nf 1 2 3\n\
+             g\nusemtl metal\nf 1 2 3\n\
+             o empty\ng unused\n",
+        );
+        let summary: Vec<(&str, &str, usize)> =
+            data.groups.iter().map(|group| (group.object.as_str(), group.name.as_str(), group.faces.len())).collect();
+        assert_eq!(summary, [("", "default", 1), ("gun", "barrel tip", 2), ("gun", "default", 1)]);
+        assert_eq!(data.face_count(), 4);
+
+        let smoothing: Vec<Option<u32>> = data.groups[1].faces.iter().map(|face| face.smoothing).collect();
+        assert_eq!(smoothing, [Some(1), Some(0)]);
+        assert_eq!(data.groups[0].faces[0].smoothing, None);
+        assert_eq!(data.groups[2].faces[0].material, Some(0));
+        assert_eq!(data.material_names, ["metal"]);
+    }
+
+    #[test]
+    fn unsupported_statements_warn_with_their_line() {
+        let data = parse("# comment\nv 0 0 0 # trailing\nl 1 1\n\ncurv 0 1 1\nv 1 0 0\n");
+        assert_eq!(data.positions.len(), 2);
+        let warnings: Vec<(usize, &str)> =
+            data.warnings.iter().map(|warning| (warning.line, warning.message.as_str())).collect();
+        assert_eq!(warnings, [(3, "unsupported statement 'l' ignored"), (5, "unsupported statement 'curv' ignored")]);
+        assert_eq!(data.warnings[0].to_string(), "test.obj:3: unsupported statement 'l' ignored");
+    }
+
+    #[test]
+    fn vertex_colors_and_w() {
+        let data = parse("v 0 0 0 1\nv 0 0 0 1 0.5 0\n");
+        assert!(data.colors[0].is_none());
+        let color = data.colors[1].unwrap();
+        assert_eq!((color.red, color.green, color.blue), (255., 127.5, 0.));
+    }
+
+    #[test]
+    fn mtllib_keeps_its_line_and_spaces() {
+        let data = parse("# header\nmtllib Portal Gun.mtl\n");
+        assert_eq!(data.material_libs, [(2, "Portal Gun.mtl".to_string())]);
+    }
+}
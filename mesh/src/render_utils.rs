@@ -1,14 +1,37 @@
+use std::fs::File;
+use std::io::BufWriter;
+use std::ops::Range;
+use std::sync::OnceLock;
+
+use image::ExtendedColorType;
+use image::ImageEncoder;
+use image::ImageResult;
+use image::RgbImage;
+use image::codecs::pnm::PnmEncoder;
+use image::codecs::pnm::PnmSubtype;
+use image::codecs::pnm::SampleEncoding;
+
+use crate::bvh::Hit;
+use crate::bvh::Ray;
 use crate::geometry::Mesh;
+use crate::lighting::Lighting;
+use crate::material::BlendMode;
 use crate::math::Floatify;
+use crate::math::Mat4;
+use crate::math::Quat;
 use crate::math::Vec3f;
+use crate::shadow::ShadowMap;
 use crate::Float;
 use crate::BACKGROUND;
+use crate::PI;
 
-#[derive(Clone, Copy)]
+/// alpha is in the same 0-255 range as the color channels, 255 being opaque
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Color {
     pub red: Float,
     pub green: Float,
     pub blue: Float,
+    pub alpha: Float,
 }
 
 impl Color {
@@ -16,29 +39,92 @@ impl Color {
     where
         T: Floatify,
     {
-        Color { red: red.floatify(), green: green.floatify(), blue: blue.floatify() }
+        Color { red: red.floatify(), green: green.floatify(), blue: blue.floatify(), alpha: 255. }
     }
 
+    pub fn cons_rgba<T>(red: T, green: T, blue: T, alpha: T) -> Color
+    where
+        T: Floatify,
+    {
+        Color { red: red.floatify(), green: green.floatify(), blue: blue.floatify(), alpha: alpha.floatify() }
+    }
+
+    /// packed as 0xaarrggbb like the window expects
     pub fn from_u32(color: u32) -> Color {
+        let alpha = (color >> 24 & 0xff) as u8;
         let red = (color >> 16 & 0xff) as u8;
         let green = (color >> 8 & 0xff) as u8;
         let blue = (color & 0xff) as u8;
-        Color::cons(red, green, blue)
+        Color::cons_rgba(red, green, blue, alpha)
     }
 
+    /// clamped rather than wrapping into the neighbouring channel
     pub fn to_u32(self) -> u32 {
-        ((self.red as u32) << 16) | ((self.green as u32) << 8) | (self.blue as u32)
+        let channel = |value: Float| value.clamp(0., 255.).round() as u32;
+        (channel(self.alpha) << 24) | (channel(self.red) << 16) | (channel(self.green) << 8) | channel(self.blue)
+    }
+
+    /// what the buffer stores, a linear color encoded to srgb
+    pub fn to_srgb_u32(self) -> u32 {
+        let table = encode_table();
+        let channel = |value: Float| {
+            let step = (value / 255. * (SRGB_ENCODE_STEPS - 1) as Float).clamp(0., (SRGB_ENCODE_STEPS - 1) as Float);
+            table[step.round() as usize] as u32
+        };
+        let alpha = self.alpha.clamp(0., 255.).round() as u32;
+        (alpha << 24) | (channel(self.red) << 16) | (channel(self.green) << 8) | channel(self.blue)
+    }
+
+    /// undoes `to_srgb_u32` for reading back out of the buffer
+    pub fn from_srgb_u32(color: u32) -> Color {
+        let table = decode_table();
+        Color::cons_rgba(
+            table[(color >> 16 & 0xff) as usize],
+            table[(color >> 8 & 0xff) as usize],
+            table[(color & 0xff) as usize],
+            (color >> 24 & 0xff) as Float,
+        )
+    }
+
+    /// srgb 0-255 to linear 0-255, alpha is always linear already
+    pub fn decode_srgb(self) -> Color {
+        let channel = |value: Float| srgb_to_linear(value / 255.) * 255.;
+        Color::cons_rgba(channel(self.red), channel(self.green), channel(self.blue), self.alpha)
     }
 
     pub fn as_vec3f(&self) -> Vec3f {
         Vec3f::cons(self.red, self.green, self.blue)
     }
 
+    pub fn lerp(&self, other: &Color, t: Float) -> Color {
+        Color::cons_rgba(
+            self.red + (other.red - self.red) * t,
+            self.green + (other.green - self.green) * t,
+            self.blue + (other.blue - self.blue) * t,
+            self.alpha + (other.alpha - self.alpha) * t,
+        )
+    }
+
+    /// channel-wise product treating 255 as 1
+    pub fn modulate(&self, other: &Color) -> Color {
+        Color::cons_rgba(
+            self.red * other.red / 255.,
+            self.green * other.green / 255.,
+            self.blue * other.blue / 255.,
+            self.alpha * other.alpha / 255.,
+        )
+    }
+
+    /// scales the color and leaves alpha alone
     pub fn attenuate(&mut self, value: Float) {
         self.red *= value;
         self.green *= value;
         self.blue *= value;
     }
+
+    pub fn with_alpha(self, alpha: Float) -> Color {
+        Color { alpha, ..self }
+    }
 }
 
 impl Default for Color {
@@ -47,33 +133,396 @@ impl Default for Color {
     }
 }
 
+/// maps linear hdr color, where 255 is 1, into what the buffer can hold
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ToneMap {
+    Clamp,
+    Reinhard,
+    Aces,
+}
+
+impl ToneMap {
+    pub fn next(self) -> ToneMap {
+        match self {
+            ToneMap::Clamp => ToneMap::Reinhard,
+            ToneMap::Reinhard => ToneMap::Aces,
+            ToneMap::Aces => ToneMap::Clamp,
+        }
+    }
+
+    /// leaves alpha alone
+    pub fn apply(self, color: Color) -> Color {
+        let curve = |value: Float| {
+            let x = value.max(0.) / 255.;
+            let mapped = match self {
+                ToneMap::Clamp => x.min(1.),
+                ToneMap::Reinhard => x / (1. + x),
+                // narkowicz's fit of the aces reference transform
+                ToneMap::Aces => ((x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14)).clamp(0., 1.),
+            };
+            mapped * 255.
+        };
+        Color::cons_rgba(curve(color.red), curve(color.green), curve(color.blue), color.alpha)
+    }
+}
+
+const SRGB_ENCODE_STEPS: usize = 4096;
+
+#[inline]
+fn srgb_to_linear(value: Float) -> Float {
+    if value <= 0.04045 {
+        value / 12.92
+    }
+    else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+#[inline]
+fn linear_to_srgb(value: Float) -> Float {
+    if value <= 0.0031308 {
+        value * 12.92
+    }
+    else {
+        1.055 * value.powf(1. / 2.4) - 0.055
+    }
+}
+
+/// linear 0-255 for every srgb byte
+fn decode_table() -> &'static [Float; 256] {
+    static TABLE: OnceLock<[Float; 256]> = OnceLock::new();
+    TABLE.get_or_init(|| std::array::from_fn(|idx| srgb_to_linear(idx as Float / 255.) * 255.))
+}
+
+/// fine enough that no neighbouring output levels get skipped
+fn encode_table() -> &'static [u8; SRGB_ENCODE_STEPS] {
+    static TABLE: OnceLock<[u8; SRGB_ENCODE_STEPS]> = OnceLock::new();
+    TABLE.get_or_init(|| {
+        std::array::from_fn(|idx| {
+            (linear_to_srgb(idx as Float / (SRGB_ENCODE_STEPS - 1) as Float) * 255.).round() as u8
+        })
+    })
+}
+
+/// supersampling shades every sample, multisampling shades once per pixel
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Antialias {
+    Off,
+    Supersample(usize),
+    Multisample(usize),
+}
+
+/// tiles still take screen coordinates, colors are stored srgb encoded
+/// output is always opaque, alpha only weights blending so saved images are plain rgb
 pub struct Buffer {
     pub height: usize,
     pub width: usize,
+    origin_x: usize,
+    origin_y: usize,
+    antialias: Antialias,
+    samples: usize,
     pixels: Vec<u32>,
     depth: Vec<Float>,
+    /// empty unless `count_overdraw` is on
+    overdraw: Vec<u16>,
+    resolved: Vec<u32>,
+    /// what `clear` fills the pixels with
+    background: u32,
 }
 
 impl Buffer {
     pub fn cons(height: usize, width: usize) -> Buffer {
-        Buffer { height, width, pixels: vec![BACKGROUND; width * height], depth: vec![1e+12; width * height] }
+        Buffer::cons_antialiased(height, width, Antialias::Off)
+    }
+
+    /// multisampling is rounded up to 2, 4 or 8 samples
+    pub fn cons_antialiased(height: usize, width: usize, antialias: Antialias) -> Buffer {
+        let antialias = match antialias {
+            Antialias::Supersample(factor) if factor > 1 => Antialias::Supersample(factor),
+            Antialias::Multisample(count) if count > 1 => Antialias::Multisample(count.next_power_of_two().min(8)),
+            _ => Antialias::Off,
+        };
+        let (factor, samples) = match antialias {
+            Antialias::Off => (1, 1),
+            Antialias::Supersample(factor) => (factor, 1),
+            Antialias::Multisample(count) => (1, count),
+        };
+        let resolved = match antialias {
+            Antialias::Off => Vec::new(),
+            _ => vec![BACKGROUND; width * height],
+        };
+
+        let (height, width) = (height * factor, width * factor);
+        Buffer {
+            height,
+            width,
+            origin_x: 0,
+            origin_y: 0,
+            antialias,
+            samples,
+            pixels: vec![BACKGROUND; width * height * samples],
+            depth: vec![1e+12; width * height * samples],
+            overdraw: Vec::new(),
+            resolved,
+            background: BACKGROUND,
+        }
+    }
+
+    /// no color at all, for things like shadow maps that only need depth
+    pub fn cons_depth(height: usize, width: usize) -> Buffer {
+        Buffer {
+            height,
+            width,
+            origin_x: 0,
+            origin_y: 0,
+            antialias: Antialias::Off,
+            samples: 1,
+            pixels: Vec::new(),
+            depth: vec![1e+12; width * height],
+            overdraw: Vec::new(),
+            resolved: Vec::new(),
+            background: BACKGROUND,
+        }
     }
 
+    /// copies out a region so whatever was already drawn there is kept
+    pub fn cons_tile(&self, x: Range<usize>, y: Range<usize>) -> Buffer {
+        let samples = self.samples;
+        let counted = if self.overdraw.is_empty() {
+            0
+        }
+        else {
+            x.len() * y.len() * samples
+        };
+        let mut tile = Buffer {
+            height: y.len(),
+            width: x.len(),
+            origin_x: x.start,
+            origin_y: y.start,
+            antialias: self.antialias,
+            samples,
+            pixels: vec![self.background; x.len() * y.len() * samples],
+            depth: vec![1e+12; x.len() * y.len() * samples],
+            overdraw: vec![0; counted],
+            resolved: Vec::new(),
+            background: self.background,
+        };
+        for row in y {
+            let (src, dst) = (self.span(row, tile.x_range()), tile.span(row, tile.x_range()));
+            tile.pixels[dst.clone()].copy_from_slice(&self.pixels[src.clone()]);
+            tile.depth[dst.clone()].copy_from_slice(&self.depth[src.clone()]);
+            if counted > 0 {
+                tile.overdraw[dst].copy_from_slice(&self.overdraw[src]);
+            }
+        }
+        tile
+    }
+
+    pub fn blit(&mut self, tile: &Buffer) {
+        for row in tile.y_range() {
+            let (src, dst) = (tile.span(row, tile.x_range()), self.span(row, tile.x_range()));
+            self.pixels[dst.clone()].copy_from_slice(&tile.pixels[src.clone()]);
+            self.depth[dst.clone()].copy_from_slice(&tile.depth[src.clone()]);
+            if !self.overdraw.is_empty() && !tile.overdraw.is_empty() {
+                self.overdraw[dst].copy_from_slice(&tile.overdraw[src]);
+            }
+        }
+    }
+
+    /// writes every sample of the pixel
     pub fn set(&mut self, x: usize, y: usize, color: Color, depth: Float) {
+        for sample in 0..self.samples {
+            self.set_sample(x, y, sample, color, depth);
+        }
+    }
+
+    pub fn set_sample(&mut self, x: usize, y: usize, sample: usize, color: Color, depth: Float) {
         {
-            debug_assert!(self.inbounds(x, y));
+            debug_assert!(self.inbounds(x, y) && sample < self.samples);
+        }
+        let idx = self.idx(x, y) * self.samples + sample;
+        if let Some(count) = self.overdraw.get_mut(idx) {
+            *count = count.saturating_add(1);
         }
-        let idx = self.idx(x, y);
         if self.depth[idx] < depth {
             return;
         }
 
         self.depth[idx] = depth;
-        self.pixels[idx] = color.to_u32();
+        self.pixels[idx] = color.with_alpha(255.).to_srgb_u32();
     }
 
+    pub fn blend(&mut self, x: usize, y: usize, color: Color, depth: Float, mode: BlendMode) {
+        for sample in 0..self.samples {
+            self.blend_sample(x, y, sample, color, depth, mode);
+        }
+    }
+
+    /// depth tested but never written
+    pub fn blend_sample(&mut self, x: usize, y: usize, sample: usize, color: Color, depth: Float, mode: BlendMode) {
+        {
+            debug_assert!(self.inbounds(x, y) && sample < self.samples);
+        }
+        let idx = self.idx(x, y) * self.samples + sample;
+        if let Some(count) = self.overdraw.get_mut(idx) {
+            *count = count.saturating_add(1);
+        }
+        if self.depth[idx] < depth {
+            return;
+        }
+
+        let under = Color::from_srgb_u32(self.pixels[idx]);
+        let coverage = (color.alpha / 255.).clamp(0., 1.);
+        let blended = match mode {
+            BlendMode::Opaque => color,
+            BlendMode::Alpha => under.lerp(&color, coverage),
+            BlendMode::Additive => Color::cons(
+                (under.red + color.red * coverage).min(255.),
+                (under.green + color.green * coverage).min(255.),
+                (under.blue + color.blue * coverage).min(255.),
+            ),
+            BlendMode::Multiply => under.lerp(&under.modulate(&color), coverage),
+        };
+        self.pixels[idx] = blended.with_alpha(255.).to_srgb_u32();
+    }
+
+    /// has to run after drawing and before the frame is shown or saved
+    pub fn resolve(&mut self) {
+        let (factor, samples) = match self.antialias {
+            Antialias::Off => return,
+            Antialias::Supersample(factor) => (factor, 1),
+            Antialias::Multisample(count) => (1, count),
+        };
+
+        let (width, height) = (self.output_width(), self.output_height());
+        for y in 0..height {
+            for x in 0..width {
+                let mut sum = Color::cons(0, 0, 0);
+                for row in y * factor..(y + 1) * factor {
+                    let start = (row * self.width + x * factor) * samples;
+                    for &pixel in &self.pixels[start..start + factor * samples] {
+                        let color = Color::from_srgb_u32(pixel);
+                        sum.red += color.red;
+                        sum.green += color.green;
+                        sum.blue += color.blue;
+                    }
+                }
+                sum.attenuate(1. / (factor * factor * samples) as Float);
+                self.resolved[y * width + x] = sum.to_srgb_u32();
+            }
+        }
+    }
+
+    /// white for the nearest sample fading to black at the farthest, linear in distance
+    pub fn show_depth(&mut self) {
+        let drawn = self.depth.iter().copied().filter(|depth| *depth < 1e+12);
+        let (near, far) = drawn.fold((Float::MAX, Float::MIN), |(near, far), depth| (near.min(depth), far.max(depth)));
+        let range = (far - near).max(1e-6);
+        for (pixel, depth) in self.pixels.iter_mut().zip(&self.depth) {
+            if *depth >= 1e+12 {
+                continue;
+            }
+            let value = ((1. - (depth - near) / range) * 255.).round() as u32;
+            *pixel = 0xff000000 | value << 16 | value << 8 | value;
+        }
+    }
+
+    /// counting costs a write per sample, so it's only on while the overdraw view is up
+    pub fn count_overdraw(&mut self, count: bool) {
+        if count {
+            self.overdraw.resize(self.pixels.len(), 0);
+        }
+        else {
+            self.overdraw = Vec::new();
+        }
+    }
+
+    /// black for never, up through blue, green, yellow and red to white at eight or more
+    pub fn show_overdraw(&mut self) {
+        const STOPS: [(u16, Color); 6] = [
+            (0, Color { red: 0., green: 0., blue: 0., alpha: 255. }),
+            (1, Color { red: 0., green: 0., blue: 255., alpha: 255. }),
+            (2, Color { red: 0., green: 200., blue: 0., alpha: 255. }),
+            (3, Color { red: 255., green: 230., blue: 0., alpha: 255. }),
+            (5, Color { red: 255., green: 0., blue: 0., alpha: 255. }),
+            (8, Color { red: 255., green: 255., blue: 255., alpha: 255. }),
+        ];
+        for (pixel, &count) in self.pixels.iter_mut().zip(&self.overdraw) {
+            let upper = STOPS.iter().position(|(stop, _)| *stop >= count).unwrap_or(STOPS.len() - 1);
+            let (high, high_color) = STOPS[upper];
+            let (low, low_color) = STOPS[upper.saturating_sub(1)];
+            let t = if high > low {
+                (count.min(high) - low) as Float / (high - low) as Float
+            }
+            else {
+                1.
+            };
+            *pixel = low_color.lerp(&high_color, t).to_u32();
+        }
+    }
+
+    pub fn set_depth(&mut self, x: usize, y: usize, depth: Float) {
+        {
+            debug_assert!(self.inbounds(x, y));
+        }
+        let idx = self.idx(x, y) * self.samples;
+        for value in &mut self.depth[idx..idx + self.samples] {
+            *value = value.min(depth);
+        }
+    }
+
+    /// the first sample's depth when multisampling
+    #[inline]
+    pub fn get_depth(&self, x: usize, y: usize) -> Float {
+        self.depth[self.idx(x, y) * self.samples]
+    }
+
+    /// the frame as it should be shown, resolved when anti-aliased
     pub fn get_pixels(&self) -> &Vec<u32> {
-        &self.pixels
+        match self.antialias {
+            Antialias::Off => &self.pixels,
+            _ => &self.resolved,
+        }
+    }
+
+    pub fn samples(&self) -> usize {
+        self.samples
+    }
+
+    pub fn output_width(&self) -> usize {
+        match self.antialias {
+            Antialias::Supersample(factor) => self.width / factor,
+            _ => self.width,
+        }
+    }
+
+    pub fn output_height(&self) -> usize {
+        match self.antialias {
+            Antialias::Supersample(factor) => self.height / factor,
+            _ => self.height,
+        }
+    }
+
+    /// the output pixels, resolved ones when anti-aliasing
+    pub fn to_image(&self) -> RgbImage {
+        let mut image = RgbImage::new(self.output_width() as u32, self.output_height() as u32);
+        for (pixel, color) in image.pixels_mut().zip(self.get_pixels().iter()) {
+            let color = Color::from_u32(*color);
+            pixel.0 = [color.red as u8, color.green as u8, color.blue as u8];
+        }
+        image
+    }
+
+    pub fn save(&self, path: &str) -> ImageResult<()> {
+        let image = self.to_image();
+
+        // image picks PAM (P7) for .ppm by default, which most viewers can't open
+        if path.ends_with(".ppm") {
+            let writer = BufWriter::new(File::create(path)?);
+            let encoder = PnmEncoder::new(writer).with_subtype(PnmSubtype::Pixmap(SampleEncoding::Binary));
+            return encoder.write_image(image.as_raw(), image.width(), image.height(), ExtendedColorType::Rgb8);
+        }
+        image.save(path)
     }
 
     pub fn get_height(&self) -> Float {
@@ -92,53 +541,576 @@ impl Buffer {
         self.get_width() / 2.
     }
 
+    /// takes effect on the next `clear`
+    pub fn set_background(&mut self, background: u32) {
+        self.background = background;
+    }
+
     pub fn clear(&mut self) {
-        self.pixels.fill(BACKGROUND);
+        self.pixels.fill(self.background);
         self.depth.fill(1e+12);
+        self.overdraw.fill(0);
     }
 
     #[inline]
     pub const fn inbounds(&self, x: usize, y: usize) -> bool {
-        x < self.width && y < self.height
+        x >= self.origin_x && y >= self.origin_y && x - self.origin_x < self.width && y - self.origin_y < self.height
+    }
+
+    #[inline]
+    pub const fn x_range(&self) -> Range<usize> {
+        self.origin_x..self.origin_x + self.width
+    }
+
+    #[inline]
+    pub const fn y_range(&self) -> Range<usize> {
+        self.origin_y..self.origin_y + self.height
     }
 
     #[inline]
     const fn idx(&self, x: usize, y: usize) -> usize {
-        self.height_inversion(y) * self.width + x
+        self.height_inversion(y) * self.width + x - self.origin_x
     }
 
     #[inline]
     const fn height_inversion(&self, y: usize) -> usize {
-        self.height - 1 - y
+        self.height - 1 - (y - self.origin_y)
+    }
+
+    /// every sample of each pixel included
+    #[inline]
+    fn span(&self, y: usize, x: Range<usize>) -> Range<usize> {
+        let start = self.idx(x.start, y) * self.samples;
+        start..start + x.len() * self.samples
+    }
+}
+
+/// isometric also pins the view to the world diagonal
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Projection {
+    Perspective,
+    Orthographic,
+    Isometric,
+}
+
+impl Projection {
+    pub fn next(self) -> Projection {
+        match self {
+            Projection::Perspective => Projection::Orthographic,
+            Projection::Orthographic => Projection::Isometric,
+            Projection::Isometric => Projection::Perspective,
+        }
+    }
+
+    pub fn is_orthographic(self) -> bool {
+        self != Projection::Perspective
     }
 }
 
+/// `yaw` turns about world z and positive `pitch` looks down
+#[derive(Debug, Clone, Copy)]
+pub struct Orbit {
+    pub target: Vec3f,
+    pub distance: Float,
+    pub yaw: Float,
+    pub pitch: Float,
+}
+
+/// `ortho_size` is half the view height, an `orbit` overrides the free movement methods
 #[allow(dead_code)]
 pub struct Camera {
     pub position: Vec3f,
-    pub rotation: Vec3f,
+    pub orientation: Quat,
+    pub projection: Projection,
+    pub near: Float,
+    pub far: Float,
+    pub ortho_size: Float,
+    pub orbit: Option<Orbit>,
 }
 
 impl Camera {
     pub fn cons(position: Vec3f) -> Camera {
-        Camera { position, rotation: Vec3f::cons(0, 0, 0) }
+        Camera {
+            position,
+            orientation: Quat::identity(),
+            projection: Projection::Perspective,
+            near: 0.1,
+            far: 10000.,
+            ortho_size: 50.,
+            orbit: None,
+        }
+    }
+
+    /// level with the horizon
+    pub fn look_at(position: Vec3f, target: Vec3f) -> Camera {
+        let mut camera = Camera::cons(position);
+        camera.face(target - position);
+        camera
+    }
+
+    pub fn face(&mut self, direction: Vec3f) {
+        let (yaw, pitch) = yaw_pitch(direction);
+        self.orientation = orientation(yaw, pitch);
     }
 
+    /// pitches about the camera's own y axis
     pub fn rotate_horizontal(&mut self, angle: Float) {
-        self.rotation.y += angle;
+        if let Some(orbit) = &mut self.orbit {
+            orbit.pitch = (orbit.pitch + angle).clamp(-MAX_PITCH, MAX_PITCH);
+            self.update_orbit();
+            return;
+        }
+        self.orientation = self.orientation * Quat::from_axis_angle(Vec3f::cons(0, 1, 0), angle);
+        self.orientation.normalize();
     }
 
+    /// yaws about world z so the horizon stays level
     pub fn rotate_vertical(&mut self, angle: Float) {
-        self.rotation.z += angle;
+        if let Some(orbit) = &mut self.orbit {
+            orbit.yaw += angle;
+            self.update_orbit();
+            return;
+        }
+        self.orientation = Quat::from_axis_angle(Vec3f::cons(0, 0, 1), angle) * self.orientation;
+        self.orientation.normalize();
     }
+
+    pub fn get_forward(&self) -> Vec3f {
+        self.orientation.rotate(Vec3f::cons(1, 0, 0))
+    }
+
+    /// moves along the ground plane using only the camera's heading
+    pub fn move_planar(&mut self, delta: Vec3f) {
+        if self.orbit.is_some() {
+            return;
+        }
+        let forward = self.get_forward();
+        let mut delta = delta;
+        delta.rot_z(forward.y.atan2(forward.x));
+        self.position += delta;
+    }
+
+    /// starts circling `target` from wherever the camera is now
+    pub fn set_orbit(&mut self, target: Vec3f) {
+        let offset = target - self.position;
+        let distance = offset.inner_prod(&offset).sqrt().max(self.near);
+        let (yaw, pitch) = yaw_pitch(offset);
+        self.orbit = Some(Orbit { target, distance, yaw, pitch });
+        self.update_orbit();
+    }
+
+    /// the orthographic size follows along so both projections zoom the same
+    pub fn zoom(&mut self, factor: Float) {
+        self.ortho_size *= factor;
+        if let Some(orbit) = &mut self.orbit {
+            orbit.distance = (orbit.distance * factor).max(self.near);
+            self.update_orbit();
+        }
+    }
+
+    /// isometric snaps onto the diagonal, around the orbit target if there is one
+    pub fn set_projection(&mut self, projection: Projection) {
+        self.projection = projection;
+        if projection != Projection::Isometric {
+            return;
+        }
+        match &mut self.orbit {
+            Some(orbit) => {
+                orbit.yaw = ISOMETRIC_YAW;
+                orbit.pitch = ISOMETRIC_PITCH;
+                self.update_orbit();
+            }
+            None => self.orientation = orientation(ISOMETRIC_YAW, ISOMETRIC_PITCH),
+        }
+    }
+
+    fn update_orbit(&mut self) {
+        let Some(orbit) = self.orbit
+        else {
+            return;
+        };
+        self.orientation = orientation(orbit.yaw, orbit.pitch);
+        self.position = orbit.target - self.get_forward() * orbit.distance;
+    }
+
+    pub fn view_matrix(&self) -> Mat4 {
+        let inv_rotation = self.orientation.to_mat3().transpose();
+        Mat4::from_mat3(inv_rotation) * Mat4::translation(-self.position)
+    }
+
+    /// z is left as the linear view depth
+    pub fn projection_matrix(&self, half_width: Float, half_height: Float, fov: Float) -> Mat4 {
+        if self.projection.is_orthographic() {
+            let scale = self.ortho_scale(half_height);
+            Mat4::cons([
+                [0., scale, 0., half_width],
+                [0., 0., -scale, half_height],
+                [1., 0., 0., 0.],
+                [0., 0., 0., 1.],
+            ])
+        }
+        else {
+            let scale = projection_scale(half_width, fov);
+            Mat4::cons([
+                [half_width, scale, 0., 0.],
+                [half_height, 0., -scale, 0.],
+                [1., 0., 0., 0.],
+                [1., 0., 0., 0.],
+            ])
+        }
+    }
+
+    /// pixels per world unit, negative like `projection_scale`
+    fn ortho_scale(&self, half_height: Float) -> Float {
+        -half_height / self.ortho_size
+    }
+
+    /// `x` and `y` are from the top left like mouse positions
+    pub fn screen_ray(&self, x: Float, y: Float, width: Float, height: Float, fov: Float) -> Ray {
+        let (half_width, half_height) = (width / 2., height / 2.);
+        let screen_y = height - y;
+        if self.projection.is_orthographic() {
+            let scale = self.ortho_scale(half_height);
+            let offset = Vec3f::cons(0., (x - half_width) / scale, (half_height - screen_y) / scale);
+            Ray::cons(self.position + self.orientation.rotate(offset), self.get_forward())
+        }
+        else {
+            let scale = projection_scale(half_width, fov);
+            let direction = Vec3f::cons(1., (x - half_width) / scale, (half_height - screen_y) / scale);
+            Ray::cons(self.position, self.orientation.rotate(direction))
+        }
+    }
+}
+
+/// a little short of straight up or down, where the yaw stops meaning anything
+const MAX_PITCH: Float = 1.55;
+const ISOMETRIC_YAW: Float = -3. * PI / 4.;
+/// atan(1 / sqrt(2)), the angle of the world diagonal below the horizon
+const ISOMETRIC_PITCH: Float = 0.6154797;
+
+/// positive pitch tips the forward axis down
+fn orientation(yaw: Float, pitch: Float) -> Quat {
+    let mut orientation =
+        Quat::from_axis_angle(Vec3f::cons(0, 0, 1), yaw) * Quat::from_axis_angle(Vec3f::cons(0, 1, 0), pitch);
+    orientation.normalize();
+    orientation
+}
+
+fn yaw_pitch(direction: Vec3f) -> (Float, Float) {
+    let level = (direction.x * direction.x + direction.y * direction.y).sqrt();
+    (direction.y.atan2(direction.x), (-direction.z).atan2(level))
 }
 
+/// pixels per view unit at depth one, negative since view y points left
+pub fn projection_scale(half_width: Float, fov: Float) -> Float {
+    -half_width / (fov / 2.).to_radians().tan()
+}
+
+pub struct SceneObject {
+    pub name: String,
+    pub mesh: Mesh,
+    pub parent: Option<String>,
+}
+
+/// `hit.t` is in world space multiples of the ray's direction
+#[derive(Debug, Clone)]
+pub struct Pick {
+    pub object: String,
+    pub hit: Hit,
+}
+
+/// a child's transform is relative to its parent, moving a mesh needs `invalidate_shadows`
 pub struct Scene {
-    objects: Vec<Mesh>,
+    objects: Vec<SceneObject>,
+    pub lighting: Lighting,
+    /// set when the shadow maps no longer match the scene
+    shadows_stale: bool,
 }
 
 impl Scene {
     pub fn new() -> Scene {
-        Scene { objects: Vec::new() }
+        Scene { objects: Vec::new(), lighting: Lighting::default(), shadows_stale: true }
+    }
+
+    /// replaces any existing object with the same name
+    pub fn add(&mut self, name: &str, mesh: Mesh) {
+        let object = SceneObject { name: name.to_string(), mesh, parent: None };
+        match self.position(name) {
+            Some(idx) => self.objects[idx] = object,
+            None => self.objects.push(object),
+        }
+        self.shadows_stale = true;
+    }
+
+    /// children of the removed object are reattached to the scene root
+    pub fn remove(&mut self, name: &str) -> Option<Mesh> {
+        let idx = self.position(name)?;
+        let object = self.objects.remove(idx);
+        self.objects
+            .iter_mut()
+            .filter(|child| child.parent.as_deref() == Some(name))
+            .for_each(|child| child.parent = None);
+        self.shadows_stale = true;
+        Some(object.mesh)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Mesh> {
+        self.objects.iter().find(|object| object.name == name).map(|object| &object.mesh)
+    }
+
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut Mesh> {
+        self.objects.iter_mut().find(|object| object.name == name).map(|object| &mut object.mesh)
+    }
+
+    /// fails if either object is missing or the link would make a cycle
+    pub fn set_parent(&mut self, child: &str, parent: Option<&str>) -> bool {
+        let Some(child_idx) = self.position(child)
+        else {
+            return false;
+        };
+        if let Some(parent) = parent {
+            if self.position(parent).is_none() {
+                return false;
+            }
+            let mut ancestor = Some(parent.to_string());
+            while let Some(name) = ancestor {
+                if name == child {
+                    return false;
+                }
+                ancestor = self.position(&name).and_then(|idx| self.objects[idx].parent.clone());
+            }
+        }
+
+        self.objects[child_idx].parent = parent.map(str::to_string);
+        self.shadows_stale = true;
+        true
+    }
+
+    /// the closest triangle any object puts in front of a world space ray
+    pub fn pick(&self, ray: &Ray) -> Option<Pick> {
+        let mut closest: Option<Pick> = None;
+        for object in &self.objects {
+            let Some(inverse) = self.world_matrix(&object.name).and_then(|model| model.inverse_affine())
+            else {
+                continue;
+            };
+            if let Some(hit) = object.mesh.closest_hit(&ray.transform(&inverse))
+                && closest.as_ref().is_none_or(|pick| hit.t < pick.hit.t)
+            {
+                closest = Some(Pick { object: object.name.clone(), hit });
+            }
+        }
+        closest
+    }
+
+    pub fn world_matrix(&self, name: &str) -> Option<Mat4> {
+        let object = &self.objects[self.position(name)?];
+        let local = object.mesh.model_matrix();
+        match &object.parent {
+            Some(parent) => Some(self.world_matrix(parent)? * local),
+            None => Some(local),
+        }
+    }
+
+    pub fn objects(&self) -> &[SceneObject] {
+        &self.objects
+    }
+
+    pub fn len(&self) -> usize {
+        self.objects.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.objects.is_empty()
+    }
+
+    fn position(&self, name: &str) -> Option<usize> {
+        self.objects.iter().position(|object| object.name == name)
+    }
+}
+
+impl Scene {
+    /// needs to happen whenever anything in the scene moves
+    pub fn update_shadows(&mut self) {
+        let maps = self.lighting.lights.iter().map(|light| ShadowMap::render(light, self)).collect();
+        self.lighting.shadow_maps = maps;
+        self.shadows_stale = false;
+    }
+
+    pub fn invalidate_shadows(&mut self) {
+        self.shadows_stale = true;
+    }
+
+    /// `update_shadows` but only when something changed since the last one
+    pub fn refresh_shadows(&mut self) {
+        if self.shadows_stale {
+            self.update_shadows();
+        }
+    }
+}
+
+impl Default for Scene {
+    fn default() -> Scene {
+        Scene::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::Tri;
+
+    const WIDTH: Float = 320.;
+    const HEIGHT: Float = 200.;
+    const FOV: Float = 70.;
+
+    /// where a world point ends up in mouse coordinates, top left first
+    fn to_mouse(camera: &Camera, point: Vec3f) -> (Float, Float) {
+        let projection = camera.projection_matrix(WIDTH / 2., HEIGHT / 2., FOV);
+        let screen = projection.project(&camera.view_matrix().transform_point(&point));
+        (screen.x, HEIGHT - screen.y)
+    }
+
+    fn cameras() -> Vec<Camera> {
+        let target = Vec3f::cons(0, 0, 0);
+        let mut orthographic = Camera::look_at(Vec3f::cons(-40, 15, 10), target);
+        orthographic.set_projection(Projection::Orthographic);
+        let mut isometric = Camera::cons(Vec3f::cons(30, 30, 30));
+        isometric.set_orbit(target);
+        isometric.set_projection(Projection::Isometric);
+        let turned = Camera::look_at(Vec3f::cons(-25, -12, 9), target);
+        vec![Camera::cons(Vec3f::cons(-30, 0, 0)), turned, orthographic, isometric]
+    }
+
+    fn unit_mesh(position: Vec3f) -> Mesh {
+        Mesh::cons(vec![Tri::cons_pos(Vec3f::cons(0, 0, 0), Vec3f::cons(0, 1, 0), Vec3f::cons(0, 0, 1))], position)
+    }
+
+    fn origin_of(scene: &Scene, name: &str) -> Vec3f {
+        scene.world_matrix(name).unwrap().transform_point(&Vec3f::cons(0, 0, 0))
+    }
+
+    fn close(lhs: Vec3f, rhs: Vec3f) -> bool {
+        let gap = lhs - rhs;
+        gap.inner_prod(&gap) < 1e-8
+    }
+
+    #[test]
+    fn adding_a_name_again_replaces_the_object() {
+        let mut scene = Scene::new();
+        scene.add("a", unit_mesh(Vec3f::cons(1, 0, 0)));
+        scene.add("b", unit_mesh(Vec3f::cons(0, 0, 0)));
+        scene.add("a", unit_mesh(Vec3f::cons(5, 0, 0)));
+        assert_eq!(scene.len(), 2);
+        assert_eq!(scene.objects()[0].name, "a");
+        assert!(close(origin_of(&scene, "a"), Vec3f::cons(5, 0, 0)));
+    }
+
+    #[test]
+    fn removing_a_parent_moves_its_children_to_the_root() {
+        let mut scene = Scene::new();
+        scene.add("parent", unit_mesh(Vec3f::cons(10, 0, 0)));
+        scene.add("child", unit_mesh(Vec3f::cons(1, 0, 0)));
+        assert!(scene.set_parent("child", Some("parent")));
+        assert!(close(origin_of(&scene, "child"), Vec3f::cons(11, 0, 0)));
+
+        assert!(scene.remove("parent").is_some());
+        assert!(scene.remove("parent").is_none());
+        assert!(scene.get("parent").is_none() && scene.world_matrix("parent").is_none());
+        assert_eq!(scene.objects()[0].parent, None);
+        assert!(close(origin_of(&scene, "child"), Vec3f::cons(1, 0, 0)));
+    }
+
+    #[test]
+    fn set_parent_rejects_cycles_and_missing_objects() {
+        let mut scene = Scene::new();
+        for name in ["a", "b", "c"] {
+            scene.add(name, unit_mesh(Vec3f::cons(0, 0, 0)));
+        }
+        assert!(scene.set_parent("b", Some("a")));
+        assert!(scene.set_parent("c", Some("b")));
+        assert!(!scene.set_parent("a", Some("b")));
+        assert!(!scene.set_parent("a", Some("c")));
+        assert!(!scene.set_parent("a", Some("a")));
+        assert!(!scene.set_parent("a", Some("nowhere")));
+        assert!(!scene.set_parent("nowhere", Some("a")));
+        assert_eq!(scene.objects()[0].parent, None);
+
+        assert!(scene.set_parent("c", None));
+        assert!(scene.set_parent("a", Some("c")));
+    }
+
+    #[test]
+    fn world_matrix_applies_the_child_before_the_parent() {
+        let mut scene = Scene::new();
+        let mut parent = unit_mesh(Vec3f::cons(10, 0, 0));
+        parent.rotate_z(PI / 2.);
+        scene.add("parent", parent);
+        scene.add("child", unit_mesh(Vec3f::cons(1, 0, 0)));
+        scene.add("grandchild", unit_mesh(Vec3f::cons(0, 0, 2)));
+        assert!(scene.set_parent("child", Some("parent")));
+        assert!(scene.set_parent("grandchild", Some("child")));
+
+        // the parent's turn swings the child's offset from +x onto +y
+        assert!(close(origin_of(&scene, "child"), Vec3f::cons(10, 1, 0)));
+        assert!(close(origin_of(&scene, "grandchild"), Vec3f::cons(10, 1, 2)));
+    }
+
+    #[test]
+    fn fov_is_the_horizontal_angle_in_degrees() {
+        let camera = Camera::cons(Vec3f::cons(0, 0, 0));
+        for fov in [30., 90., 120.] {
+            let projection = camera.projection_matrix(WIDTH / 2., HEIGHT / 2., fov);
+            // points on the edges of the view at depth 10, +y is to the left
+            let side = 10. * (fov / 2.).to_radians().tan();
+            let left = projection.project(&camera.view_matrix().transform_point(&Vec3f::cons(10., side, 0.)));
+            let right = projection.project(&camera.view_matrix().transform_point(&Vec3f::cons(10., -side, 0.)));
+            assert!(left.x.abs() < 1e-3 && (right.x - WIDTH).abs() < 1e-3, "{}: {:?} {:?}", fov, left, right);
+        }
+    }
+
+    #[test]
+    fn screen_ray_passes_through_projected_points() {
+        let points = [Vec3f::cons(0, 0, 0), Vec3f::cons(3, -4, 2), Vec3f::cons(-2, 5, -3), Vec3f::cons(4, 4, 4)];
+        for camera in cameras() {
+            for point in points {
+                let (x, y) = to_mouse(&camera, point);
+                let ray = camera.screen_ray(x, y, WIDTH, HEIGHT, FOV);
+                let along = (point - ray.origin).inner_prod(&ray.direction) / ray.direction.inner_prod(&ray.direction);
+                let miss = ray.at(along) - point;
+                assert!(along > 0., "{:?} is behind the ray from {:?}", point, camera.position);
+                assert!(miss.inner_prod(&miss) < 1e-4, "ray through ({}, {}) misses {:?} by {:?}", x, y, point, miss);
+            }
+        }
+    }
+
+    #[test]
+    fn picks_the_triangle_a_point_was_projected_from() {
+        let mut scene = Scene::new();
+        let near = Tri::cons_pos(Vec3f::cons(-2, 2, -2), Vec3f::cons(-2, -2, -2), Vec3f::cons(-2, 0, 2));
+        let far = Tri::cons_pos(Vec3f::cons(6, 9, -2), Vec3f::cons(6, 5, -2), Vec3f::cons(6, 7, 2));
+        scene.add("near", Mesh::cons(vec![near], Vec3f::cons(0, 0, 0)));
+        scene.add("far", Mesh::cons(vec![far], Vec3f::cons(0, 0, 0)));
+
+        for camera in cameras() {
+            for (name, tri) in [("near", near), ("far", far)] {
+                let center = (tri.a.pos + tri.b.pos + tri.c.pos) / 3.;
+                let (x, y) = to_mouse(&camera, center);
+                let ray = camera.screen_ray(x, y, WIDTH, HEIGHT, FOV);
+                let Some(pick) = scene.pick(&ray)
+                else {
+                    panic!("nothing picked at ({}, {}) looking for '{}'", x, y, name);
+                };
+                // the other triangle can only win by being in front
+                let along = (center - ray.origin).inner_prod(&ray.direction) / ray.direction.inner_prod(&ray.direction);
+                let miss = ray.at(pick.hit.t) - center;
+                if pick.object == name {
+                    assert!(miss.inner_prod(&miss) < 1e-4, "picked '{}' off its center", name);
+                }
+                else {
+                    assert!(pick.hit.t < along, "'{}' picked through '{}'", pick.object, name);
+                }
+            }
+        }
     }
 }
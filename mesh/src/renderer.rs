@@ -1,207 +1,837 @@
+use std::array::from_fn;
+use std::mem::swap;
+use std::ops::Range;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::thread::scope;
+use std::time::Duration;
+use std::time::Instant;
+
+use crate::bounds::CullStats;
 use crate::geometry::BarycentricSystem;
+use crate::geometry::CullMode;
 use crate::geometry::Mesh;
+use crate::geometry::Plane;
 use crate::geometry::PolyData;
 use crate::geometry::RefFrame;
 use crate::geometry::Tri;
+use crate::lighting::LightPass;
+use crate::lighting::LightSample;
+use crate::lighting::Lighting;
+use crate::material::BlendMode;
+use crate::material::Material;
+use crate::math::Mat3;
+use crate::math::Mat4;
+use crate::math::Quat;
+use crate::math::Vec2f;
 use crate::math::Vec2i;
 use crate::math::Vec3f;
 use crate::render_utils::Buffer;
 use crate::render_utils::Camera;
 use crate::render_utils::Color;
+use crate::render_utils::Pick;
+use crate::render_utils::Scene;
+use crate::render_utils::ToneMap;
+use crate::texture::Filter;
+use crate::tiles::TileGrid;
 use crate::Float;
 use crate::Int;
 
+/// an object's index and its visible triangles in view space, with their world normals
+type ViewTris = (usize, Vec<(usize, Tri, Vec3f)>);
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Interpolation {
+    Affine,
+    Perspective,
+}
+
+/// per pixel picks up point light falloff and highlights inside a face
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Shading {
+    PerVertex,
+    PerPixel,
+}
+
+/// vertex normals are gouraud with per vertex shading and phong with per pixel
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Normals {
+    Face,
+    Vertex,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RasterMode {
+    Bresenham,
+    EdgeFunction,
+}
+
+/// normals are in view space, depth and overdraw are read back out of the buffer
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ViewMode {
+    Shaded,
+    Depth,
+    Normals,
+    Uvs,
+    TriangleIds,
+    Overdraw,
+}
+
+impl ViewMode {
+    pub fn next(self) -> ViewMode {
+        match self {
+            ViewMode::Shaded => ViewMode::Depth,
+            ViewMode::Depth => ViewMode::Normals,
+            ViewMode::Normals => ViewMode::Uvs,
+            ViewMode::Uvs => ViewMode::TriangleIds,
+            ViewMode::TriangleIds => ViewMode::Overdraw,
+            ViewMode::Overdraw => ViewMode::Shaded,
+        }
+    }
+}
+
+/// kept between frames, `threads` above 1 bins triangles into `tile_size` squares
+#[derive(Clone, Copy)]
+pub struct RenderSettings {
+    pub interpolation: Interpolation,
+    pub raster: RasterMode,
+    pub filter: Filter,
+    pub shading: Shading,
+    pub normals: Normals,
+    pub shadows: bool,
+    pub tone_map: ToneMap,
+    pub view: ViewMode,
+    pub threads: usize,
+    pub tile_size: usize,
+}
+
+impl RenderSettings {
+    pub fn toggle_interpolation(&mut self) {
+        self.interpolation = match self.interpolation {
+            Interpolation::Affine => Interpolation::Perspective,
+            Interpolation::Perspective => Interpolation::Affine,
+        };
+    }
+
+    pub fn toggle_raster(&mut self) {
+        self.raster = match self.raster {
+            RasterMode::Bresenham => RasterMode::EdgeFunction,
+            RasterMode::EdgeFunction => RasterMode::Bresenham,
+        };
+    }
+
+    pub fn toggle_shading(&mut self) {
+        self.shading = match self.shading {
+            Shading::PerVertex => Shading::PerPixel,
+            Shading::PerPixel => Shading::PerVertex,
+        };
+    }
+
+    pub fn toggle_normals(&mut self) {
+        self.normals = match self.normals {
+            Normals::Face => Normals::Vertex,
+            Normals::Vertex => Normals::Face,
+        };
+    }
+
+    pub fn toggle_shadows(&mut self) {
+        self.shadows = !self.shadows;
+    }
+
+    pub fn cycle_filter(&mut self) {
+        self.filter = self.filter.next();
+    }
+
+    pub fn cycle_tone_map(&mut self) {
+        self.tone_map = self.tone_map.next();
+    }
+
+    pub fn cycle_view(&mut self) {
+        self.view = self.view.next();
+    }
+}
+
+impl Default for RenderSettings {
+    fn default() -> RenderSettings {
+        RenderSettings {
+            interpolation: Interpolation::Perspective,
+            raster: RasterMode::Bresenham,
+            filter: Filter::Trilinear,
+            shading: Shading::PerVertex,
+            normals: Normals::Vertex,
+            shadows: true,
+            tone_map: ToneMap::Clamp,
+            view: ViewMode::Shaded,
+            threads: 1,
+            tile_size: 64,
+        }
+    }
+}
+
+/// setup is clipping, projection, culling and vertex lighting, fill is everything per pixel
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StageTimes {
+    pub transform: Duration,
+    pub setup: Duration,
+    pub fill: Duration,
+    /// triangles that made it past frustum culling
+    pub tris: usize,
+    /// what reached the rasterizer after clipping and backface culling
+    pub polys: usize,
+}
+
+impl StageTimes {
+    pub fn total(&self) -> Duration {
+        self.transform + self.setup + self.fill
+    }
+}
+
 #[allow(dead_code)]
 pub struct Renderer<'d> {
     buffer: &'d mut Buffer,
-    mesh: &'d Mesh,
+    scene: &'d Scene,
     camera: &'d Camera,
-    lighting_vec: Vec3f,
-    scale: Float,
-    overdraw_percent: Float,
-    minimum_lighting: Float,
+    materials: &'d [Material],
+    settings: RenderSettings,
+    rasterizer: Rasterizer<'d>,
+    model: Mat4,
+    model_view: Mat4,
+    normal_matrix: Mat3,
+    projection: Mat4,
+    cull: CullMode,
+    stats: CullStats,
+    times: StageTimes,
+    near: Float,
+    frustum: [Plane; 6],
 }
 
 impl<'d> Renderer<'d> {
-    pub fn cons(buffer: &'d mut Buffer, mesh: &'d Mesh, camera: &'d Camera, fov: Float) -> Renderer<'d> {
-        let mut lighting_vec = Vec3f::cons(-3, 1, -4);
-        lighting_vec.normalize();
-        let scale = buffer.get_half_width() / (fov / 2.).to_degrees().tan();
-        let overdraw_percent = 0.20;
-        let minimum_lighting = 0.15;
+    pub fn cons(
+        buffer: &'d mut Buffer, scene: &'d Scene, camera: &'d Camera, fov: Float, settings: RenderSettings,
+    ) -> Renderer<'d> {
+        let (near, far) = (camera.near, camera.far);
+        let width = buffer.get_width();
+        let height = buffer.get_height();
+        let projection = camera.projection_matrix(buffer.get_half_width(), buffer.get_half_height(), fov);
+        buffer.count_overdraw(settings.view == ViewMode::Overdraw);
+
+        // each screen edge is where the x or y row of the projection equals the w row
+        let (x, y, w) = (projection.m[0], projection.m[1], projection.m[3]);
+        let inside = |bound: Float, row: [Float; 4], sign: Float| {
+            let plane: [Float; 4] = from_fn(|idx| w[idx] * bound + row[idx] * sign);
+            Plane::cons(Vec3f::cons(plane[0], plane[1], plane[2]), plane[3])
+        };
+        let frustum = [
+            Plane::cons(Vec3f::cons(1., 0., 0.), -near),
+            inside(0., x, 1.),
+            inside(width, x, -1.),
+            inside(height, y, -1.),
+            inside(0., y, 1.),
+            Plane::cons(Vec3f::cons(-1., 0., 0.), far),
+        ];
+
+        Renderer {
+            buffer,
+            scene,
+            camera,
+            materials: &[],
+            settings,
+            rasterizer: Rasterizer::cons(settings, &scene.lighting, camera),
+            model: Mat4::identity(),
+            model_view: Mat4::identity(),
+            normal_matrix: Mat3::identity(),
+            projection,
+            cull: CullMode::Back,
+            stats: CullStats::default(),
+            times: StageTimes::default(),
+            near,
+            frustum,
+        }
+    }
+
+    /// every mesh in the scene goes through the same depth buffer, each stage is timed on its own
+    pub fn render_scene(&mut self) {
+        let start = Instant::now();
+        let transformed = self.transform_scene();
+        self.times.transform = start.elapsed();
+        self.times.tris = transformed.iter().map(|(_, tris)| tris.len()).sum();
+
+        let start = Instant::now();
+        let polys = self.setup_scene(&transformed);
+        self.times.setup = start.elapsed();
+        self.times.polys = polys.len();
+
+        // opaque first in submission order, then transparent back to front
+        let start = Instant::now();
+        let objects = self.scene.objects();
+        let material_of = |(mesh, poly): &(usize, PolyData)| &objects[*mesh].mesh.materials[poly.tri.material];
+        let (mut polys, mut transparent): (Vec<_>, Vec<_>) =
+            polys.into_iter().partition(|entry| !material_of(entry).is_transparent());
+        sort_back_to_front(&mut transparent);
+        polys.append(&mut transparent);
+        if self.settings.threads > 1 {
+            self.fill_tiled(&polys);
+        }
+        else {
+            for entry in &polys {
+                self.rasterizer.rasterize(self.buffer, &entry.1, material_of(entry));
+            }
+        }
+        self.times.fill = start.elapsed();
+
+        self.finish_view();
+    }
+
+    /// how long each stage of the last `render_scene` took
+    pub fn stage_times(&self) -> StageTimes {
+        self.times
+    }
+
+    /// frustum culls each object and moves what's left into view space
+    fn transform_scene(&mut self) -> Vec<ViewTris> {
+        let mut transformed = Vec::new();
+        for (idx, object) in self.scene.objects().iter().enumerate() {
+            let model = self.scene.world_matrix(&object.name).unwrap_or(Mat4::identity());
+            self.bind_mesh(&object.mesh, &model);
+            let (visible, stats) = self.visible_tris(&object.mesh);
+            self.stats.add(&stats);
+
+            let ids: Vec<usize> = visible.into_iter().flatten().collect();
+            let tris = self.split_work(&ids, |&id| {
+                let (triangle, world_norm) = self.transform_triangle(&object.mesh.tris[id]);
+                [(id, triangle, world_norm)]
+            });
+            transformed.push((idx, tris));
+        }
+        transformed
+    }
+
+    /// clipping, projection, culling and vertex lighting, tagged with the object each poly came from
+    fn setup_scene(&mut self, transformed: &[ViewTris]) -> Vec<(usize, PolyData)> {
+        let objects = self.scene.objects();
+        let mut polys = Vec::new();
+        for (idx, tris) in transformed {
+            let model = self.scene.world_matrix(&objects[*idx].name).unwrap_or(Mat4::identity());
+            self.bind_mesh(&objects[*idx].mesh, &model);
+            polys.extend(self.split_work(tris, |&(id, triangle, world_norm)| {
+                self.setup_triangle(id, triangle, world_norm).into_iter().map(|poly| (*idx, poly))
+            }));
+        }
+        polys
+    }
+
+    /// spread over `threads` in contiguous runs so the results keep submission order
+    fn split_work<T, I>(&self, items: &[T], work: impl Fn(&T) -> I + Sync) -> Vec<I::Item>
+    where
+        T: Sync,
+        I: IntoIterator,
+        I::Item: Send,
+    {
+        let threads = self.settings.threads;
+        if threads <= 1 {
+            return items.iter().flat_map(work).collect();
+        }
 
-        Renderer { buffer, mesh, camera, lighting_vec, scale, overdraw_percent, minimum_lighting }
+        let (chunk, work) = (items.len().div_ceil(threads).max(1), &work);
+        scope(|scope| {
+            let handles: Vec<_> = items
+                .chunks(chunk)
+                .map(|items| scope.spawn(move || items.iter().flat_map(work).collect::<Vec<_>>()))
+                .collect();
+            handles.into_iter().flat_map(|handle| handle.join().unwrap()).collect()
+        })
     }
 
-    pub fn render_mesh(&mut self) {
-        self.mesh.tris.iter().for_each(|tri| {
-            self.render_triangle(tri);
+    /// the debug views that are read back out of the buffer once it's drawn
+    fn finish_view(&mut self) {
+        match self.settings.view {
+            ViewMode::Depth => self.buffer.show_depth(),
+            ViewMode::Overdraw => self.buffer.show_overdraw(),
+            _ => (),
+        }
+    }
+
+    pub fn cull_stats(&self) -> CullStats {
+        self.stats
+    }
+
+    pub fn set_view(&mut self, view: ViewMode) {
+        self.settings.view = view;
+        self.rasterizer.settings.view = view;
+        self.buffer.count_overdraw(view == ViewMode::Overdraw);
+    }
+
+    /// bit-identical to drawing in one go since every tile sees the polys in the same order
+    fn fill_tiled(&mut self, polys: &[(usize, PolyData)]) {
+        let threads = self.settings.threads;
+
+        let mut grid = TileGrid::cons(self.buffer.width, self.buffer.height, self.settings.tile_size);
+        for (idx, (_, poly)) in polys.iter().enumerate() {
+            grid.bin(idx, &poly.tri);
+        }
+
+        let next_tile = AtomicUsize::new(0);
+        let (buffer, scene, rasterizer) = (&*self.buffer, self.scene, self.rasterizer);
+        let tiles: Vec<Buffer> = scope(|scope| {
+            let handles: Vec<_> = (0..threads)
+                .map(|_| {
+                    scope.spawn(|| {
+                        let mut finished = Vec::new();
+                        loop {
+                            let tile = next_tile.fetch_add(1, Ordering::Relaxed);
+                            if tile >= grid.len() {
+                                return finished;
+                            }
+                            if grid.bin_of(tile).is_empty() {
+                                continue;
+                            }
+
+                            let (x, y) = grid.tile_bounds(tile);
+                            let mut target = buffer.cons_tile(x, y);
+                            for &idx in grid.bin_of(tile) {
+                                let (mesh, poly) = &polys[idx];
+                                let material = &scene.objects()[*mesh].mesh.materials[poly.tri.material];
+                                rasterizer.rasterize(&mut target, poly, material);
+                            }
+                            finished.push(target);
+                        }
+                    })
+                })
+                .collect();
+            handles.into_iter().flat_map(|handle| handle.join().unwrap()).collect()
         });
+
+        tiles.iter().for_each(|tile| self.buffer.blit(tile));
     }
 
     pub fn render_wireframe(&mut self) {
-        self.mesh.tris.iter().for_each(|tri| {
-            if let Some(polydata) = self.initialize_triangle_render(tri) {
-                let mut color = Color::cons(0, 255, 255);
-                color.attenuate(polydata.lighting);
-                self.draw_line_screen(polydata.tri.a.pos, polydata.tri.b.pos, color);
-                self.draw_line_screen(polydata.tri.a.pos, polydata.tri.c.pos, color);
-                self.draw_line_screen(polydata.tri.c.pos, polydata.tri.b.pos, color);
-            }
-        });
+        for object in self.scene.objects() {
+            let model = self.scene.world_matrix(&object.name).unwrap_or(Mat4::identity());
+            self.bind_mesh(&object.mesh, &model);
+            let (visible, _) = self.visible_tris(&object.mesh);
+            visible.into_iter().flatten().for_each(|id| {
+                for polydata in self.initialize_triangle_render(id, &object.mesh.tris[id]) {
+                    let mut color = Color::cons(0, 255, 255);
+                    color.attenuate(polydata.shading[0].add(&polydata.shadowed[0]).luminance().min(1.));
+                    self.draw_line_screen(polydata.tri.a.pos, polydata.tri.b.pos, color);
+                    self.draw_line_screen(polydata.tri.a.pos, polydata.tri.c.pos, color);
+                    self.draw_line_screen(polydata.tri.c.pos, polydata.tri.b.pos, color);
+                }
+            });
+        }
     }
 
-    pub fn render_refframe(&mut self, frame: &RefFrame) {
-        let mut frame = *frame;
+    /// outlines a picked triangle on top of everything, back faces included
+    pub fn render_highlight(&mut self, pick: &Pick) {
+        let (Some(mesh), Some(model)) = (self.scene.get(&pick.object), self.scene.world_matrix(&pick.object))
+        else {
+            return;
+        };
+        let Some(tri) = mesh.tris.get(pick.hit.tri)
+        else {
+            return;
+        };
 
-        let mut x_arm = Vec3f::cons(frame.length, 0., 0.);
-        let mut y_arm = Vec3f::cons(0., frame.length, 0.);
-        let mut z_arm = Vec3f::cons(0., 0., frame.length);
+        let model_view = self.camera.view_matrix() * model;
+        let [a, b, c] = [tri.a.pos, tri.b.pos, tri.c.pos].map(|pos| model_view.transform_point(&pos));
+        let color = Color::cons(255, 255, 0);
+        self.draw_line_world(a, b, color);
+        self.draw_line_world(b, c, color);
+        self.draw_line_world(c, a, color);
+    }
 
-        x_arm.rot_zyx(-self.camera.rotation);
-        y_arm.rot_zyx(-self.camera.rotation);
-        z_arm.rot_zyx(-self.camera.rotation);
+    pub fn render_refframe(&mut self, frame: &RefFrame, rotation: Quat) {
+        let view = self.camera.view_matrix();
 
-        x_arm.rot_zyx(self.mesh.rotation);
-        y_arm.rot_zyx(self.mesh.rotation);
-        z_arm.rot_zyx(self.mesh.rotation);
+        let x_arm = rotation.rotate(Vec3f::cons(frame.length, 0., 0.)) + frame.center;
+        let y_arm = rotation.rotate(Vec3f::cons(0., frame.length, 0.)) + frame.center;
+        let z_arm = rotation.rotate(Vec3f::cons(0., 0., frame.length)) + frame.center;
 
-        frame.translate(-self.camera.position);
-        x_arm += frame.center;
-        y_arm += frame.center;
-        z_arm += frame.center;
+        let center = view.transform_point(&frame.center);
+        self.draw_line_world(center, view.transform_point(&x_arm), Color::cons(255, 0, 0));
+        self.draw_line_world(center, view.transform_point(&y_arm), Color::cons(0, 255, 0));
+        self.draw_line_world(center, view.transform_point(&z_arm), Color::cons(0, 0, 255));
+    }
 
-        self.draw_line_world(frame.center, x_arm, Color::cons(255, 0, 0));
-        self.draw_line_world(frame.center, y_arm, Color::cons(0, 255, 0));
-        self.draw_line_world(frame.center, z_arm, Color::cons(0, 0, 255));
+    fn bind_mesh(&mut self, mesh: &'d Mesh, model: &Mat4) {
+        self.materials = &mesh.materials;
+        self.cull = mesh.cull;
+        self.model = *model;
+        self.model_view = self.camera.view_matrix() * *model;
+        self.normal_matrix = model.to_mat3().normal_matrix();
     }
 
-    fn render_triangle(&mut self, tri: &Tri) {
-        if let Some(polydata) = self.initialize_triangle_render(tri) {
-            let (a, b, c) = (polydata.tri.a.pos, polydata.tri.b.pos, polydata.tri.c.pos);
-            if polydata.tri.lumped_left() {
-                self.trace_and_fill(&polydata, a, c, a, b);
-                self.trace_and_fill(&polydata, c, a, c, b);
+    /// none when the whole mesh is off screen
+    fn visible_tris(&self, mesh: &Mesh) -> (Vec<Range<usize>>, CullStats) {
+        let mut stats = CullStats { objects: 1, ..Default::default() };
+        let sphere = mesh.sphere.transform(&self.model_view);
+        let inside = sphere.intersects_frustum(&self.frustum)
+            && mesh.bounds.intersects_frustum(&self.model_view, &self.frustum);
+        if !inside {
+            stats.objects_culled = 1;
+            return (Vec::new(), stats);
+        }
+        if mesh.chunks.is_empty() || sphere.inside_frustum(&self.frustum) {
+            return (std::iter::once(0..mesh.tris.len()).collect(), stats);
+        }
+
+        let mut visible = Vec::new();
+        for chunk in &mesh.chunks {
+            stats.chunks += 1;
+            let inside = chunk.sphere.transform(&self.model_view).intersects_frustum(&self.frustum)
+                && chunk.bounds.intersects_frustum(&self.model_view, &self.frustum);
+            if inside {
+                visible.push(chunk.tris.clone());
             }
             else {
-                self.trace_and_fill(&polydata, a, b, a, c);
-                self.trace_and_fill(&polydata, c, b, c, a);
+                stats.chunks_culled += 1;
             }
         }
+        (visible, stats)
     }
 
-    fn initialize_triangle_render(&mut self, tri: &Tri) -> Option<PolyData> {
+    fn initialize_triangle_render(&self, id: usize, tri: &Tri) -> Vec<PolyData> {
+        let (triangle, world_norm) = self.transform_triangle(tri);
+        self.setup_triangle(id, triangle, world_norm)
+    }
+
+    /// the triangle in view space along with its world space face normal
+    fn transform_triangle(&self, tri: &Tri) -> (Tri, Vec3f) {
         let mut triangle: Tri = *tri;
+        let world_norm = (self.normal_matrix * triangle.get_normal()).get_normalized();
+        self.transform_tri(&mut triangle, world_norm);
+        (triangle, world_norm)
+    }
+
+    fn setup_triangle(&self, id: usize, triangle: Tri, world_norm: Vec3f) -> Vec<PolyData> {
+        let material = &self.materials[triangle.material];
+        let cull = material.cull.unwrap_or(self.cull);
 
-        // super super needs to be changed! haven't done lighting yet and this is a
-        // major bottleneck at the current moment. def a better way to do this, maybe have
-        // PolyData hold two norms world and viewframe and can be stored during poly calcs
-        // so norm doesn't have to be done like 3 times for no reason
-        let mut world_norm = triangle.get_normal();
-        world_norm.rot_zyx(self.mesh.rotation);
-        let lighting = self.lighting_vec.inner_prod(&world_norm).max(self.minimum_lighting);
+        // winding is checked on the projected pieces, before sorting the verts loses it
+        triangle
+            .clip(&self.frustum)
+            .into_iter()
+            .filter_map(|mut clipped| {
+                self.transform_to_screen(&mut clipped);
+                if cull.culls(clipped.screen_area()) {
+                    return None;
+                }
+                clipped.sort_verts_vertical();
+                let verts = [clipped.a, clipped.b, clipped.c];
+                let shade = |pass| verts.map(|vert| self.rasterizer.shade(vert.world, vert.normal, material, pass));
+                let (shading, shadowed) = match self.settings.shading {
+                    Shading::PerVertex => (shade(LightPass::Unshadowed), shade(LightPass::Shadowed)),
+                    Shading::PerPixel => ([LightSample::default(); 3], [LightSample::default(); 3]),
+                };
+                Some(PolyData::cons(clipped, world_norm, shading, shadowed, id))
+            })
+            .collect()
+    }
 
-        self.transform_tri(&mut triangle);
+    fn transform_to_screen(&self, triangle: &mut Tri) {
+        triangle.a.pos = self.view_to_screen(&triangle.a.pos);
+        triangle.b.pos = self.view_to_screen(&triangle.b.pos);
+        triangle.c.pos = self.view_to_screen(&triangle.c.pos);
+    }
 
-        let norm = triangle.get_normal();
-        if norm.x > self.overdraw_percent {
-            return None;
+    /// the face normal stands in when vertex normals are off or missing
+    fn transform_tri(&self, triangle: &mut Tri, face_normal: Vec3f) {
+        for vert in [&mut triangle.a, &mut triangle.b, &mut triangle.c] {
+            vert.world = self.model.transform_point(&vert.pos);
+            vert.normal = if self.settings.normals == Normals::Vertex && vert.has_normal() {
+                (self.normal_matrix * vert.normal).get_normalized()
+            }
+            else {
+                face_normal
+            };
         }
+        triangle.transform(&self.model_view);
+    }
 
-        self.transform_to_screen(&mut triangle);
-        if triangle.behind_view() {
-            return None;
+    fn draw_line_screen(&mut self, p1: Vec3f, p2: Vec3f, color: Color) {
+        let mut edge = EdgeTracer::cons(p1, p2);
+        while let Some(point) = edge.step_once() {
+            if point.x < 0 || point.y < 0 || !self.buffer.inbounds(point.x as usize, point.y as usize) {
+                continue;
+            }
+
+            self.buffer.set(point.x as usize, point.y as usize, color, 1.);
+        }
+    }
+
+    fn draw_line_world(&mut self, mut p1: Vec3f, mut p2: Vec3f, color: Color) {
+        if p1.x < self.near && p2.x < self.near {
+            return;
+        }
+        if p1.x < self.near {
+            p1 = p1.lerp(&p2, (self.near - p1.x) / (p2.x - p1.x));
         }
+        if p2.x < self.near {
+            p2 = p2.lerp(&p1, (self.near - p2.x) / (p1.x - p2.x));
+        }
+
+        let p1 = self.view_to_screen(&p1);
+        let p2 = self.view_to_screen(&p2);
+        self.draw_line_screen(p1, p2, color);
+    }
 
-        triangle.sort_verts_vertical();
+    fn view_to_screen(&self, target: &Vec3f) -> Vec3f {
+        self.projection.project(target)
+    }
+}
+
+/// the per-pixel half of the pipeline, one copy is shared by every tile thread
+#[derive(Clone, Copy)]
+pub struct Rasterizer<'d> {
+    settings: RenderSettings,
+    lighting: &'d Lighting,
+    eye: Vec3f,
+    /// world to view rotation, only for the normals view
+    view_rotation: Mat3,
+    shadows: bool,
+    /// plain barycentrics are already perspective correct
+    orthographic: bool,
+}
+
+impl<'d> Rasterizer<'d> {
+    pub fn cons(settings: RenderSettings, lighting: &'d Lighting, camera: &Camera) -> Rasterizer<'d> {
+        let shadows = settings.shadows && lighting.has_shadows();
+        let view_rotation = camera.view_matrix().to_mat3();
+        let orthographic = camera.projection.is_orthographic();
+        Rasterizer { settings, lighting, eye: camera.position, view_rotation, shadows, orthographic }
+    }
+
+    #[inline]
+    pub fn shade(&self, point: Vec3f, normal: Vec3f, material: &Material, pass: LightPass) -> LightSample {
+        self.lighting.shade(point, normal, self.eye, material, pass, self.shadows)
+    }
 
-        Some(PolyData::cons(triangle, norm, lighting))
+    /// multisampling always uses the edge functions, only they give sample coverage
+    pub fn rasterize(&self, buffer: &mut Buffer, poly: &PolyData, material: &Material) {
+        if buffer.samples() > 1 {
+            self.fill_multisample(buffer, poly, material);
+            return;
+        }
+        if self.settings.raster == RasterMode::EdgeFunction {
+            self.fill_edge_function(buffer, poly, material);
+            return;
+        }
+
+        let (a, b, c) = (poly.tri.a.pos, poly.tri.b.pos, poly.tri.c.pos);
+        if poly.tri.lumped_left() {
+            self.trace_and_fill(buffer, poly, material, a, c, a, b);
+            self.trace_and_fill(buffer, poly, material, c, a, c, b);
+        }
+        else {
+            self.trace_and_fill(buffer, poly, material, a, b, a, c);
+            self.trace_and_fill(buffer, poly, material, c, b, c, a);
+        }
     }
 
-    fn trace_and_fill(&mut self, poly: &PolyData, e1s: Vec3f, e1e: Vec3f, e2s: Vec3f, e2e: Vec3f) {
+    #[allow(clippy::too_many_arguments)]
+    fn trace_and_fill(
+        &self, buffer: &mut Buffer, poly: &PolyData, material: &Material, e1s: Vec3f, e1e: Vec3f, e2s: Vec3f,
+        e2e: Vec3f,
+    ) {
         let mut e1 = EdgeTracer::cons(e1s, e1e);
         let mut e2 = EdgeTracer::cons(e2s, e2e);
         let barycentric = BarycentricSystem::cons(&poly.tri);
         while let (Some(p1), Some(p2)) = (e1.step_constant(), e2.step_constant()) {
-            self.fill_edge_trace(&p1, &p2, poly, &barycentric);
+            self.fill_edge_trace(buffer, &p1, &p2, poly, material, &barycentric);
         }
     }
 
-    fn transform_to_screen(&self, triangle: &mut Tri) {
-        triangle.a.pos = self.view_to_screen(&triangle.a.pos);
-        triangle.b.pos = self.view_to_screen(&triangle.b.pos);
-        triangle.c.pos = self.view_to_screen(&triangle.c.pos);
-        triangle.a.pos.clamp_xy(0., self.buffer.get_width(), 0., self.buffer.get_height());
-        triangle.b.pos.clamp_xy(0., self.buffer.get_width(), 0., self.buffer.get_height());
-        triangle.c.pos.clamp_xy(0., self.buffer.get_width(), 0., self.buffer.get_height());
-    }
-
-    fn transform_tri(&mut self, triangle: &mut Tri) {
-        triangle.rot_xyz(self.mesh.rotation);
-        triangle.translate(-self.camera.position);
-        triangle.translate(self.mesh.center);
-        triangle.rot_zyx(-self.camera.rotation);
-    }
-
     fn fill_edge_trace(
-        &mut self, starting: &Vec2i, ending: &Vec2i, poly: &PolyData, bary: &BarycentricSystem,
+        &self, buffer: &mut Buffer, starting: &Vec2i, ending: &Vec2i, poly: &PolyData, material: &Material,
+        bary: &BarycentricSystem,
     ) {
         {
             debug_assert!(starting.y == ending.y);
         }
 
+        // clamped since clipped verts can round a pixel past the edge of the screen or tile
+        let (xs, ys) = (buffer.x_range(), buffer.y_range());
         let y = starting.y;
-        for x in starting.x..=ending.x {
-            if !self.buffer.inbounds(x as usize, y as usize) {
-                return;
-            }
+        if y < ys.start as Int || y >= ys.end as Int {
+            return;
+        }
 
-            let mut color = Color::default();
-            let coords = bary.get_coords(x, y);
-            let texture_x = poly.tri.interpolate_tex_u(&coords);
-            let texture_y = poly.tri.interpolate_tex_v(&coords);
+        for x in starting.x.max(xs.start as Int)..=ending.x.min(xs.end as Int - 1) {
+            self.shade_pixel(buffer, x, y, poly, material, bary);
+        }
+    }
 
-            if let Some(texture) = &self.mesh.texture {
-                color = texture.get_texture(texture_x, texture_y);
-            }
+    fn fill_edge_function(&self, buffer: &mut Buffer, poly: &PolyData, material: &Material) {
+        let Some(edges) = EdgeFunction::cons(&poly.tri)
+        else {
+            return;
+        };
+
+        let barycentric = BarycentricSystem::cons(&poly.tri);
+        let (xs, ys) = (buffer.x_range(), buffer.y_range());
+        edges.for_each_covered(xs, ys, |x, y| {
+            self.shade_pixel(buffer, x, y, poly, material, &barycentric);
+        });
+    }
+
+    /// shades once at the pixel center, depth is still per sample
+    fn fill_multisample(&self, buffer: &mut Buffer, poly: &PolyData, material: &Material) {
+        let Some(edges) = EdgeFunction::cons(&poly.tri)
+        else {
+            return;
+        };
+
+        let barycentric = BarycentricSystem::cons(&poly.tri);
+        let pattern = sample_pattern(buffer.samples());
+        let (xs, ys) = (buffer.x_range(), buffer.y_range());
+        edges.for_each_covered_multisample(xs, ys, pattern, |x, y, mask| {
+            let Some(color) = self.shade_fragment(x, y, poly, material, &barycentric)
             else {
-                color.red = poly.tri.get_red_ordered_vec().inner_prod(&coords);
-                color.green = poly.tri.get_green_ordered_vec().inner_prod(&coords);
-                color.blue = poly.tri.get_blue_ordered_vec().inner_prod(&coords);
+                return;
+            };
+
+            for (sample, &(offset_x, offset_y)) in pattern.iter().enumerate() {
+                if mask & (1 << sample) == 0 {
+                    continue;
+                }
+                let (sample_x, sample_y) = (x as Float + from_fixed(offset_x), y as Float + from_fixed(offset_y));
+                let coords = barycentric.get_coords_at(sample_x, sample_y);
+                let depth = self.depth(poly, coords);
+                match material.blend {
+                    BlendMode::Opaque => buffer.set_sample(x as usize, y as usize, sample, color, depth),
+                    blend => buffer.blend_sample(x as usize, y as usize, sample, color, depth, blend),
+                }
             }
+        });
+    }
 
-            color.attenuate(poly.lighting);
-            let depth = poly.tri.interpolate_depth_nonlinear(coords);
+    #[inline]
+    fn shade_pixel(
+        &self, buffer: &mut Buffer, x: Int, y: Int, poly: &PolyData, material: &Material, bary: &BarycentricSystem,
+    ) {
+        let Some(color) = self.shade_fragment(x, y, poly, material, bary)
+        else {
+            return;
+        };
+        let depth = self.depth(poly, bary.get_coords(x, y));
 
-            self.buffer.set(x as usize, y as usize, color, depth);
+        match material.blend {
+            BlendMode::Opaque => buffer.set(x as usize, y as usize, color, depth),
+            blend => buffer.blend(x as usize, y as usize, color, depth, blend),
         }
     }
 
-    fn draw_line_screen(&mut self, p1: Vec3f, p2: Vec3f, color: Color) {
-        let mut edge = EdgeTracer::cons(p1, p2);
-        while let Some(point) = edge.step_once() {
-            if !self.buffer.inbounds(point.x as usize, point.y as usize) {
-                return;
+    /// none if the alpha test throws the pixel away
+    #[inline]
+    fn shade_fragment(
+        &self, x: Int, y: Int, poly: &PolyData, material: &Material, bary: &BarycentricSystem,
+    ) -> Option<Color> {
+        let coords = bary.get_coords(x, y);
+        let weights = self.weights(bary, &coords);
+        let texpos = interpolate_texpos(&poly.tri, &weights);
+        if self.settings.view != ViewMode::Shaded {
+            return Some(self.debug_color(poly, &weights, texpos));
+        }
+
+        // the neighbouring pixels' uvs stand in for screen space derivatives
+        let lod = match material.diffuse_map {
+            Some(_) => {
+                let ddx = interpolate_texpos(&poly.tri, &self.weights(bary, &bary.get_coords(x + 1, y))) - texpos;
+                let ddy = interpolate_texpos(&poly.tri, &self.weights(bary, &bary.get_coords(x, y + 1))) - texpos;
+                material.diffuse_lod(ddx, ddy)
             }
+            None => 0.,
+        };
+        let filter = material.filter.unwrap_or(self.settings.filter);
 
-            self.buffer.set(point.x as usize, point.y as usize, color, 1.);
+        let vertex_color = Color::cons(
+            poly.tri.get_red_ordered_vec().inner_prod(&weights),
+            poly.tri.get_green_ordered_vec().inner_prod(&weights),
+            poly.tri.get_blue_ordered_vec().inner_prod(&weights),
+        );
+        let albedo = material.sample_diffuse(texpos, lod, filter, vertex_color);
+        if material.alpha_cutoff.is_some_and(|cutoff| albedo.alpha < cutoff * 255.) {
+            return None;
         }
+
+        let tri = &poly.tri;
+        let world = || tri.a.world * weights.x + tri.b.world * weights.y + tri.c.world * weights.z;
+        let normal = || self.normal(poly, &weights);
+        let light = match self.settings.shading {
+            Shading::PerVertex if self.shadows => {
+                let shadowed = LightSample::lerp3(&poly.shadowed, &weights);
+                let visibility = self.lighting.visibility(world(), normal());
+                LightSample::lerp3(&poly.shading, &weights).add(&shadowed.scale(visibility))
+            }
+            Shading::PerVertex => LightSample::lerp3(&poly.shading, &weights),
+            Shading::PerPixel => self.shade(world(), normal(), material, LightPass::All),
+        };
+        Some(self.settings.tone_map.apply(light.apply(albedo, material.specular)))
     }
 
-    fn draw_line_world(&mut self, p1: Vec3f, p2: Vec3f, color: Color) {
-        let p1 = self.view_to_screen(&p1);
-        let p2 = self.view_to_screen(&p2);
-        self.draw_line_screen(p1, p2, color);
+    /// world space and unit length
+    #[inline]
+    fn normal(&self, poly: &PolyData, weights: &Vec3f) -> Vec3f {
+        let tri = &poly.tri;
+        match self.settings.normals {
+            Normals::Face => poly.world_normal,
+            Normals::Vertex => {
+                (tri.a.normal * weights.x + tri.b.normal * weights.y + tri.c.normal * weights.z).get_normalized()
+            }
+        }
     }
 
-    fn view_to_screen(&self, target: &Vec3f) -> Vec3f {
-        let scrx = target.y / target.x * self.scale + self.buffer.get_half_width();
-        let scry = -target.z / target.x * self.scale + self.buffer.get_half_height();
+    /// decoded so they come back out of the buffer's srgb encode unchanged
+    fn debug_color(&self, poly: &PolyData, weights: &Vec3f, texpos: Vec2f) -> Color {
+        let color = match self.settings.view {
+            ViewMode::Normals => {
+                let normal = self.view_rotation * self.normal(poly, weights);
+                let channel = |value: Float| (value * 0.5 + 0.5) * 255.;
+                Color::cons(channel(normal.y), channel(normal.z), channel(-normal.x))
+            }
+            ViewMode::Uvs => Color::cons(texpos.x.rem_euclid(1.) * 255., texpos.y.rem_euclid(1.) * 255., 0.),
+            ViewMode::TriangleIds => {
+                let hash = (poly.id as u32).wrapping_add(1).wrapping_mul(0x9e3779b1);
+                Color::from_u32(hash | 0xff000000)
+            }
+            // overwritten from the buffer's own data once the scene is done
+            ViewMode::Shaded | ViewMode::Depth | ViewMode::Overdraw => Color::cons(255, 255, 255),
+        };
+        color.decode_srgb()
+    }
+
+    #[inline]
+    fn weights(&self, bary: &BarycentricSystem, coords: &Vec3f) -> Vec3f {
+        if self.orthographic || self.settings.interpolation == Interpolation::Affine {
+            *coords
+        }
+        else {
+            bary.perspective_correct(coords)
+        }
+    }
 
-        Vec3f::cons(scrx, scry, target.x)
+    #[inline]
+    fn depth(&self, poly: &PolyData, coords: Vec3f) -> Float {
+        if self.orthographic {
+            poly.tri.interpolate_depth_linear(coords)
+        }
+        else {
+            poly.tri.interpolate_depth_nonlinear(coords)
+        }
     }
 }
 
+/// farthest first by the mean view depth of the verts, ties keep their order
+fn sort_back_to_front<T>(polys: &mut [(T, PolyData)]) {
+    let depth = |poly: &PolyData| poly.tri.a.pos.z + poly.tri.b.pos.z + poly.tri.c.pos.z;
+    polys.sort_by(|(_, a), (_, b)| depth(b).total_cmp(&depth(a)));
+}
+
+#[inline]
+fn interpolate_texpos(tri: &Tri, weights: &Vec3f) -> Vec2f {
+    Vec2f::cons(tri.interpolate_tex_u(weights), tri.interpolate_tex_v(weights))
+}
+
 pub struct EdgeTracer {
     current: Vec2i,
     target: Vec2i,
@@ -266,3 +896,303 @@ impl EdgeTracer {
         Some(self.current)
     }
 }
+
+const SUBPIXEL_BITS: u32 = 4;
+const SUBPIXEL_ONE: i64 = 1 << SUBPIXEL_BITS;
+
+/// half-space rasterizer on 28.4 fixed point verts, edges follow the top-left rule
+pub struct EdgeFunction {
+    edges: [EdgeEquation; 3],
+    min: Vec2i,
+    max: Vec2i,
+}
+
+#[derive(Clone, Copy)]
+struct EdgeEquation {
+    a: i64,
+    b: i64,
+    c: i64,
+}
+
+impl EdgeEquation {
+    /// positive on the left of `start -> end`, left and top edges own the samples on them
+    fn cons(start: (i64, i64), end: (i64, i64)) -> EdgeEquation {
+        let a = start.1 - end.1;
+        let b = end.0 - start.0;
+        let owned = a > 0 || (a == 0 && b < 0);
+        let bias = if owned {
+            0
+        }
+        else {
+            -1
+        };
+        EdgeEquation { a, b, c: start.0 * end.1 - start.1 * end.0 + bias }
+    }
+
+    #[inline]
+    fn evaluate(&self, x: i64, y: i64) -> i64 {
+        self.a * x + self.b * y + self.c
+    }
+}
+
+impl EdgeFunction {
+    pub fn cons(tri: &Tri) -> Option<EdgeFunction> {
+        let fixed = |pos: Vec3f| {
+            ((pos.x * SUBPIXEL_ONE as Float).round() as i64, (pos.y * SUBPIXEL_ONE as Float).round() as i64)
+        };
+        let (a, mut b, mut c) = (fixed(tri.a.pos), fixed(tri.b.pos), fixed(tri.c.pos));
+
+        let area = (b.0 - a.0) * (c.1 - a.1) - (b.1 - a.1) * (c.0 - a.0);
+        if area == 0 {
+            return None;
+        }
+        if area < 0 {
+            swap(&mut b, &mut c);
+        }
+
+        let min_x = a.0.min(b.0).min(c.0);
+        let max_x = a.0.max(b.0).max(c.0);
+        let min_y = a.1.min(b.1).min(c.1);
+        let max_y = a.1.max(b.1).max(c.1);
+
+        Some(EdgeFunction {
+            edges: [EdgeEquation::cons(a, b), EdgeEquation::cons(b, c), EdgeEquation::cons(c, a)],
+            min: Vec2i::cons(ceil_fixed(min_x) as Int, ceil_fixed(min_y) as Int),
+            max: Vec2i::cons(max_x.div_euclid(SUBPIXEL_ONE) as Int, max_y.div_euclid(SUBPIXEL_ONE) as Int),
+        })
+    }
+
+    pub fn for_each_covered<F>(&self, xs: Range<usize>, ys: Range<usize>, mut func: F)
+    where
+        F: FnMut(Int, Int),
+    {
+        let min_x = self.min.x.max(xs.start as Int);
+        let max_x = self.max.x.min(xs.end as Int - 1);
+        let min_y = self.min.y.max(ys.start as Int);
+        let max_y = self.max.y.min(ys.end as Int - 1);
+        if min_x > max_x || min_y > max_y {
+            return;
+        }
+
+        let [e0, e1, e2] = self.edges;
+        let (start_x, start_y) = (min_x as i64 * SUBPIXEL_ONE, min_y as i64 * SUBPIXEL_ONE);
+        let mut row = [e0.evaluate(start_x, start_y), e1.evaluate(start_x, start_y), e2.evaluate(start_x, start_y)];
+        let step_x = [e0.a * SUBPIXEL_ONE, e1.a * SUBPIXEL_ONE, e2.a * SUBPIXEL_ONE];
+        let step_y = [e0.b * SUBPIXEL_ONE, e1.b * SUBPIXEL_ONE, e2.b * SUBPIXEL_ONE];
+
+        for y in min_y..=max_y {
+            let mut w = row;
+            for x in min_x..=max_x {
+                if (w[0] | w[1] | w[2]) >= 0 {
+                    func(x, y);
+                }
+                w[0] += step_x[0];
+                w[1] += step_x[1];
+                w[2] += step_x[2];
+            }
+            row[0] += step_y[0];
+            row[1] += step_y[1];
+            row[2] += step_y[2];
+        }
+    }
+
+    /// calls back with a bit set for every sample of `pattern` the triangle covers
+    pub fn for_each_covered_multisample<F>(
+        &self, xs: Range<usize>, ys: Range<usize>, pattern: &[(i64, i64)], mut func: F,
+    ) where
+        F: FnMut(Int, Int, u32),
+    {
+        let min_x = (self.min.x - 1).max(xs.start as Int);
+        let max_x = (self.max.x + 1).min(xs.end as Int - 1);
+        let min_y = (self.min.y - 1).max(ys.start as Int);
+        let max_y = (self.max.y + 1).min(ys.end as Int - 1);
+        if min_x > max_x || min_y > max_y {
+            return;
+        }
+
+        let offsets: Vec<[i64; 3]> = pattern
+            .iter()
+            .map(|&(x, y)| self.edges.map(|edge| edge.a * x + edge.b * y))
+            .collect();
+        let [e0, e1, e2] = self.edges;
+        let (start_x, start_y) = (min_x as i64 * SUBPIXEL_ONE, min_y as i64 * SUBPIXEL_ONE);
+        let mut row = [e0.evaluate(start_x, start_y), e1.evaluate(start_x, start_y), e2.evaluate(start_x, start_y)];
+        let step_x = [e0.a * SUBPIXEL_ONE, e1.a * SUBPIXEL_ONE, e2.a * SUBPIXEL_ONE];
+        let step_y = [e0.b * SUBPIXEL_ONE, e1.b * SUBPIXEL_ONE, e2.b * SUBPIXEL_ONE];
+
+        for y in min_y..=max_y {
+            let mut w = row;
+            for x in min_x..=max_x {
+                let mut mask = 0;
+                for (sample, offset) in offsets.iter().enumerate() {
+                    if ((w[0] + offset[0]) | (w[1] + offset[1]) | (w[2] + offset[2])) >= 0 {
+                        mask |= 1 << sample;
+                    }
+                }
+                if mask != 0 {
+                    func(x, y, mask);
+                }
+                w[0] += step_x[0];
+                w[1] += step_x[1];
+                w[2] += step_x[2];
+            }
+            row[0] += step_y[0];
+            row[1] += step_y[1];
+            row[2] += step_y[2];
+        }
+    }
+}
+
+/// rotated grids in fixed point steps from the pixel center
+fn sample_pattern(count: usize) -> &'static [(i64, i64)] {
+    match count {
+        2 => &[(4, 4), (-4, -4)],
+        4 => &[(-2, -6), (6, -2), (-6, 2), (2, 6)],
+        8 => &[(1, -3), (-1, 3), (5, 1), (-3, -5), (-5, 5), (-7, -1), (3, 7), (7, -7)],
+        _ => &[(0, 0)],
+    }
+}
+
+#[inline]
+fn from_fixed(fixed: i64) -> Float {
+    fixed as Float / SUBPIXEL_ONE as Float
+}
+
+#[inline]
+fn ceil_fixed(value: i64) -> i64 {
+    (value + SUBPIXEL_ONE - 1).div_euclid(SUBPIXEL_ONE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bounds::CHUNK_SIZE;
+    use crate::testing::Lcg;
+
+    fn tri(a: (Float, Float), b: (Float, Float), c: (Float, Float)) -> Tri {
+        Tri::cons_pos(Vec3f::cons(a.0, a.1, 1.), Vec3f::cons(b.0, b.1, 1.), Vec3f::cons(c.0, c.1, 1.))
+    }
+
+    /// how many triangles covered every pixel of a `size` x `size` screen
+    fn coverage(tris: &[Tri], size: usize) -> Vec<u32> {
+        let mut counts = vec![0; size * size];
+        for tri in tris {
+            if let Some(edges) = EdgeFunction::cons(tri) {
+                edges.for_each_covered(0..size, 0..size, |x, y| counts[y as usize * size + x as usize] += 1);
+            }
+        }
+        counts
+    }
+
+    /// small triangles at view depth `depth`, each chunk of them `spacing` further along y
+    fn strip(depth: Float, count: usize, spacing: Float) -> Mesh {
+        let tris = (0..count)
+            .map(|idx| {
+                let y = (idx / CHUNK_SIZE) as Float * spacing - spacing;
+                let corner = |dy: Float, dz: Float| Vec3f::cons(depth, y + dy, dz);
+                Tri::cons_pos(corner(0., 0.), corner(0.5, 0.), corner(0., 0.5))
+            })
+            .collect();
+        Mesh::cons(tris, Vec3f::cons(0, 0, 0))
+    }
+
+    #[test]
+    fn culling_counts_objects_and_chunks() {
+        let mut scene = Scene::new();
+        scene.add("ahead", strip(10., 4, 0.));
+        scene.add("behind", strip(-50., 4, 0.));
+        // three chunks side by side, only the middle one is on screen
+        scene.add("wide", strip(30., CHUNK_SIZE * 3, 1000.));
+        let camera = Camera::cons(Vec3f::cons(0, 0, 0));
+
+        for threads in [1, 4] {
+            let mut buffer = Buffer::cons(60, 80);
+            let settings = RenderSettings { threads, shadows: false, ..Default::default() };
+            let mut renderer = Renderer::cons(&mut buffer, &scene, &camera, 90., settings);
+            renderer.render_scene();
+            let stats = renderer.cull_stats();
+            assert_eq!((stats.objects, stats.objects_culled), (3, 1), "with {} threads", threads);
+            assert_eq!((stats.chunks, stats.chunks_culled), (3, 2), "with {} threads", threads);
+        }
+    }
+
+    #[test]
+    fn pixel_aligned_square_follows_top_left_rule() {
+        let quad = [tri((0., 0.), (4., 0.), (4., 4.)), tri((0., 0.), (4., 4.), (0., 4.))];
+        let counts = coverage(&quad, 8);
+
+        for y in 0..8 {
+            for x in 0..8 {
+                let expected = u32::from((0..4).contains(&x) && (1..5).contains(&y));
+                assert_eq!(counts[y * 8 + x], expected, "pixel {},{}", x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn winding_does_not_change_coverage() {
+        let ccw = coverage(&[tri((1.3, 0.7), (9.6, 2.1), (4.2, 8.8))], 12);
+        let cw = coverage(&[tri((1.3, 0.7), (4.2, 8.8), (9.6, 2.1))], 12);
+        assert_eq!(ccw, cw);
+    }
+
+    #[test]
+    fn fan_around_subpixel_center_is_watertight() {
+        let center = (16.3125, 15.8125);
+        let spokes = 37;
+        let mut fan = Vec::new();
+        for idx in 0..spokes {
+            let angle = |i: usize| i as Float / spokes as Float * std::f32::consts::TAU;
+            let (s0, c0) = angle(idx).sin_cos();
+            let (s1, c1) = angle(idx + 1).sin_cos();
+            let (start, end) = ((center.0 + 40. * c0, center.1 + 40. * s0), (center.0 + 40. * c1, center.1 + 40. * s1));
+            fan.push(tri(center, start, end));
+        }
+
+        // the fan reaches past the screen on every side so every pixel is inside it
+        assert!(coverage(&fan, 32).iter().all(|count| *count == 1));
+    }
+
+    #[test]
+    fn jittered_grid_is_watertight() {
+        let (cells, cell_size, size) = (12, 5., 64);
+        let mut random = Lcg(0x5eed);
+
+        // border verts only slide along it so the union stays [2, 62) x [2, 62)
+        let mut verts = vec![vec![(0., 0.); cells + 1]; cells + 1];
+        for (j, row) in verts.iter_mut().enumerate() {
+            for (i, vert) in row.iter_mut().enumerate() {
+                let jitter_x = if i == 0 || i == cells { 0. } else { random.next() * 3. - 1.5 };
+                let jitter_y = if j == 0 || j == cells { 0. } else { random.next() * 3. - 1.5 };
+                *vert = (2. + i as Float * cell_size + jitter_x, 2. + j as Float * cell_size + jitter_y);
+            }
+        }
+
+        let mut mesh = Vec::new();
+        for j in 0..cells {
+            for i in 0..cells {
+                let (a, b, c, d) = (verts[j][i], verts[j][i + 1], verts[j + 1][i + 1], verts[j + 1][i]);
+                // alternate the diagonal so both directions of shared edge get exercised
+                if (i + j) % 2 == 0 {
+                    mesh.push(tri(a, b, c));
+                    mesh.push(tri(a, c, d));
+                }
+                else {
+                    mesh.push(tri(a, b, d));
+                    mesh.push(tri(b, c, d));
+                }
+            }
+        }
+
+        let counts = coverage(&mesh, size);
+        for y in 0..size {
+            for x in 0..size {
+                let count = counts[y * size + x];
+                assert!(count <= 1, "pixel {},{} drawn {} times", x, y, count);
+                if (3..62).contains(&x) && (3..62).contains(&y) {
+                    assert_eq!(count, 1, "crack at pixel {},{}", x, y);
+                }
+            }
+        }
+    }
+}
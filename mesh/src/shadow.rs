@@ -0,0 +1,310 @@
+use crate::bounds::Aabb;
+use crate::geometry::BarycentricSystem;
+use crate::geometry::Plane;
+use crate::geometry::Tri;
+use crate::lighting::Light;
+use crate::lighting::LightKind;
+use crate::math::Mat4;
+use crate::math::Vec3f;
+use crate::render_utils::Buffer;
+use crate::render_utils::Scene;
+use crate::renderer::EdgeFunction;
+use crate::Float;
+
+/// biases and `pcf_radius` are in shadow map texels so they hold up at any scene size
+#[derive(Debug, Clone, Copy)]
+pub struct ShadowSettings {
+    pub resolution: usize,
+    pub bias: Float,
+    pub slope_bias: Float,
+    pub pcf_radius: usize,
+}
+
+/// spot light shadows stop where the light has faded to this
+const FADED: Float = 1. / 256.;
+
+/// every shadowed light redraws the scene at this size whenever it changes
+impl Default for ShadowSettings {
+    fn default() -> ShadowSettings {
+        ShadowSettings { resolution: 2048, bias: 1.5, slope_bias: 3., pcf_radius: 1 }
+    }
+}
+
+/// orthographic for directional lights, perspective for spot lights
+pub struct ShadowMap {
+    depth: Buffer,
+    view: Mat4,
+    projection: Mat4,
+    orthographic: bool,
+    /// world size of one texel, per unit of depth for perspective maps
+    texel: Float,
+    settings: ShadowSettings,
+}
+
+impl ShadowMap {
+    /// point lights would need a cube of maps and don't cast shadows
+    pub fn render(light: &Light, scene: &Scene) -> Option<ShadowMap> {
+        let settings = light.shadow?;
+        let size = settings.resolution.max(1);
+        let half = size as Float / 2.;
+
+        let mut tris = Vec::new();
+        for object in scene.objects() {
+            let model = scene.world_matrix(&object.name).unwrap_or(Mat4::identity());
+            tris.extend(object.mesh.tris.iter().map(|tri| {
+                let mut tri = *tri;
+                tri.transform(&model);
+                tri
+            }));
+        }
+
+        let mut frustum = None;
+        let mut map = match light.kind {
+            LightKind::Directional { direction } => {
+                let bounds = Aabb::from_tris(&tris);
+                if bounds.is_empty() {
+                    return None;
+                }
+                let (center, extent) = (bounds.center(), bounds.size());
+                let radius = (extent.inner_prod(&extent).sqrt() / 2.).max(1e-3);
+                let scale = half / radius;
+                ShadowMap {
+                    depth: Buffer::cons_depth(size, size),
+                    view: look_along(center - direction * (radius + 1.), direction),
+                    projection: Mat4::cons([
+                        [0., scale, 0., half],
+                        [0., 0., -scale, half],
+                        [1., 0., 0., 0.],
+                        [0., 0., 0., 1.],
+                    ]),
+                    orthographic: true,
+                    texel: 1. / scale,
+                    settings,
+                }
+            }
+            LightKind::Spot { position, direction, outer, attenuation, .. } => {
+                let focal = half / outer.min(1.5).tan();
+                let mut planes = vec![
+                    Plane::cons(Vec3f::cons(1., 0., 0.), -0.1),
+                    Plane::cons(Vec3f::cons(half, focal, 0.), 0.),
+                    Plane::cons(Vec3f::cons(half, -focal, 0.), 0.),
+                    Plane::cons(Vec3f::cons(half, 0., focal), 0.),
+                    Plane::cons(Vec3f::cons(half, 0., -focal), 0.),
+                ];
+                if let Some(range) = attenuation.range(FADED / light.intensity) {
+                    planes.push(Plane::cons(Vec3f::cons(-1., 0., 0.), range.max(0.1)));
+                }
+                frustum = Some(planes);
+                ShadowMap {
+                    depth: Buffer::cons_depth(size, size),
+                    view: look_along(position, direction),
+                    projection: Mat4::cons([
+                        [half, focal, 0., 0.],
+                        [half, 0., -focal, 0.],
+                        [1., 0., 0., 0.],
+                        [1., 0., 0., 0.],
+                    ]),
+                    orthographic: false,
+                    texel: 1. / focal,
+                    settings,
+                }
+            }
+            LightKind::Point { .. } => return None,
+        };
+
+        map.rasterize(&tris, frustum.as_deref());
+        Some(map)
+    }
+
+    /// fraction of the pcf kernel that sees the light, `lambert` drives the slope bias
+    pub fn visibility(&self, point: Vec3f, lambert: Float) -> Float {
+        let view = self.view.transform_point(&point);
+        if !self.orthographic && view.x <= 0. {
+            return 1.;
+        }
+
+        let screen = self.projection.project(&view);
+        let texel = if self.orthographic {
+            self.texel
+        }
+        else {
+            self.texel * view.x
+        };
+        let slope = ((1. - lambert * lambert).max(0.).sqrt() / lambert.max(1e-3)).min(10.);
+        let depth = view.x - texel * (self.settings.bias + self.settings.slope_bias * slope);
+
+        let radius = self.settings.pcf_radius as i64;
+        let (center_x, center_y) = (screen.x.round() as i64, screen.y.round() as i64);
+        let mut lit = 0;
+        for y in center_y - radius..=center_y + radius {
+            for x in center_x - radius..=center_x + radius {
+                // outside of the map counts as lit, nothing was drawn there
+                let inside = x >= 0 && y >= 0 && self.depth.inbounds(x as usize, y as usize);
+                if !inside || self.depth.get_depth(x as usize, y as usize) >= depth {
+                    lit += 1;
+                }
+            }
+        }
+
+        lit as Float / ((2 * radius + 1) * (2 * radius + 1)) as Float
+    }
+
+    /// both sides go in so thin geometry still blocks light
+    fn rasterize(&mut self, tris: &[Tri], frustum: Option<&[Plane]>) {
+        for tri in tris {
+            let mut tri = *tri;
+            tri.transform(&self.view);
+            let clipped = match frustum {
+                Some(frustum) => tri.clip(frustum),
+                None => vec![tri],
+            };
+
+            for mut tri in clipped {
+                tri.a.pos = self.projection.project(&tri.a.pos);
+                tri.b.pos = self.projection.project(&tri.b.pos);
+                tri.c.pos = self.projection.project(&tri.c.pos);
+                let Some(edges) = EdgeFunction::cons(&tri)
+                else {
+                    continue;
+                };
+
+                let barycentric = BarycentricSystem::cons(&tri);
+                let (xs, ys) = (self.depth.x_range(), self.depth.y_range());
+                let orthographic = self.orthographic;
+                let depth = &mut self.depth;
+                edges.for_each_covered(xs, ys, |x, y| {
+                    let coords = barycentric.get_coords(x, y);
+                    let value = if orthographic {
+                        tri.interpolate_depth_linear(coords)
+                    }
+                    else {
+                        tri.interpolate_depth_nonlinear(coords)
+                    };
+                    depth.set_depth(x as usize, y as usize, value);
+                });
+            }
+        }
+    }
+}
+
+/// world to light space, laid out like view space
+fn look_along(origin: Vec3f, direction: Vec3f) -> Mat4 {
+    let forward = direction.get_normalized();
+    let hint = if forward.z.abs() > 0.99 {
+        Vec3f::cons(1, 0, 0)
+    }
+    else {
+        Vec3f::cons(0, 0, 1)
+    };
+    let up = (hint - forward * hint.inner_prod(&forward)).get_normalized();
+    let left = up.cross(&forward);
+
+    Mat4::cons([
+        [forward.x, forward.y, forward.z, -forward.inner_prod(&origin)],
+        [left.x, left.y, left.z, -left.inner_prod(&origin)],
+        [up.x, up.y, up.z, -up.inner_prod(&origin)],
+        [0., 0., 0., 1.],
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::Mesh;
+    use crate::lighting::Attenuation;
+    use crate::render_utils::Color;
+    use crate::testing::Lcg;
+    use crate::PI;
+
+    /// a wall facing the light at distance `x`, much wider than the cone
+    fn wall(x: Float) -> Mesh {
+        let corner = |y: Float, z: Float| Vec3f::cons(x, y, z);
+        let tris = vec![
+            Tri::cons_pos(corner(-1000., -1000.), corner(1000., -1000.), corner(1000., 1000.)),
+            Tri::cons_pos(corner(-1000., -1000.), corner(1000., 1000.), corner(-1000., 1000.)),
+        ];
+        Mesh::cons(tris, Vec3f::cons(0, 0, 0))
+    }
+
+    /// a square of two triangles spanned by `u` and `v` around `center`
+    fn plate(center: Vec3f, u: Vec3f, v: Vec3f) -> Mesh {
+        let corner = |su: Float, sv: Float| center + u * su + v * sv;
+        let tris = vec![
+            Tri::cons_pos(corner(-1., -1.), corner(1., -1.), corner(1., 1.)),
+            Tri::cons_pos(corner(-1., -1.), corner(1., 1.), corner(-1., 1.)),
+        ];
+        Mesh::cons(tris, Vec3f::cons(0, 0, 0))
+    }
+
+    fn sun(settings: ShadowSettings) -> Light {
+        let mut light = Light::directional(Vec3f::cons(1, 0, 0), Color::cons(255, 255, 255), 1.);
+        light.shadow = Some(settings);
+        light
+    }
+
+    #[test]
+    fn directional_shadows_fall_behind_the_occluder() {
+        let (y, z) = (Vec3f::cons(0, 1, 0), Vec3f::cons(0, 0, 1));
+        let mut scene = Scene::new();
+        scene.add("blocker", plate(Vec3f::cons(0, 0, 0), y, z));
+        scene.add("floor", plate(Vec3f::cons(10, 0, 0), y * 5., z * 5.));
+
+        let settings = ShadowSettings { resolution: 256, ..Default::default() };
+        let map = ShadowMap::render(&sun(settings), &scene).unwrap();
+        assert_eq!(map.visibility(Vec3f::cons(10, 0, 0), 1.), 0.);
+        assert_eq!(map.visibility(Vec3f::cons(10., 0.5, -0.5), 1.), 0.);
+        assert_eq!(map.visibility(Vec3f::cons(10, 4, 4), 1.), 1.);
+        assert_eq!(map.visibility(Vec3f::cons(10, -3, 0), 1.), 1.);
+        assert_eq!(map.visibility(Vec3f::cons(0, 0, 0), 1.), 1.);
+        assert_eq!(map.visibility(Vec3f::cons(-5, 0, 0), 1.), 1.);
+
+        // the kernel straddles the shadow's edge, a single texel can't
+        let edge = Vec3f::cons(10., 1., 0.);
+        let soft = map.visibility(edge, 1.);
+        assert!(soft > 0. && soft < 1., "{}", soft);
+        let hard = ShadowSettings { pcf_radius: 0, ..settings };
+        let map = ShadowMap::render(&sun(hard), &scene).unwrap();
+        let visibility = map.visibility(edge, 1.);
+        assert!(visibility == 0. || visibility == 1., "{}", visibility);
+    }
+
+    #[test]
+    fn bias_keeps_sloped_surfaces_from_shadowing_themselves() {
+        // a plate turned 60 degrees away from the light
+        let (sin, cos) = (PI / 3.).sin_cos();
+        let (u, v) = (Vec3f::cons(sin, cos, 0.) * 10., Vec3f::cons(0., 0., 10.));
+        let lambert = Vec3f::cons(-cos, sin, 0.).inner_prod(&Vec3f::cons(-1, 0, 0));
+        let mut scene = Scene::new();
+        scene.add("slope", plate(Vec3f::cons(0, 0, 0), u, v));
+
+        let mut rng = Lcg(21);
+        let points: Vec<Vec3f> = (0..200).map(|_| u * rng.range(-0.8, 0.8) + v * rng.range(-0.8, 0.8)).collect();
+        let settings = ShadowSettings { resolution: 128, ..Default::default() };
+        let map = ShadowMap::render(&sun(settings), &scene).unwrap();
+        for point in &points {
+            assert_eq!(map.visibility(*point, lambert), 1., "acne at {:?}", point);
+        }
+
+        let unbiased = ShadowSettings { bias: 0., slope_bias: 0., ..settings };
+        let map = ShadowMap::render(&sun(unbiased), &scene).unwrap();
+        let acne = points.iter().filter(|point| map.visibility(**point, lambert) < 1.).count();
+        assert!(acne > points.len() / 4, "only {} of {} points caught acne without bias", acne, points.len());
+    }
+
+    #[test]
+    fn spot_shadows_end_where_the_light_fades() {
+        let attenuation = Attenuation::cons(1., 0., 0.01);
+        let range = attenuation.range(FADED).unwrap();
+        assert!((1. / (1. + 0.01 * range * range) - FADED).abs() < 1e-6);
+
+        let white = Color::cons(255, 255, 255);
+        let mut light = Light::spot(Vec3f::cons(0, 0, 0), Vec3f::cons(1, 0, 0), 0.3, 0.5, attenuation, white, 1.);
+        light.shadow = Some(ShadowSettings { resolution: 64, ..Default::default() });
+        for (distance, visibility) in [(range * 0.5, 0.), (range * 1.5, 1.)] {
+            let mut scene = Scene::new();
+            scene.add("wall", wall(distance));
+            let map = ShadowMap::render(&light, &scene).unwrap();
+            assert_eq!(map.visibility(Vec3f::cons(distance + 10., 0., 0.), 1.), visibility, "wall at {}", distance);
+        }
+    }
+}
@@ -0,0 +1,23 @@
+//! helpers shared by the tests of several modules
+
+use crate::math::Vec3f;
+use crate::Float;
+
+/// tiny deterministic generator so randomized tests are the same every run
+pub struct Lcg(pub u64);
+
+impl Lcg {
+    /// uniform in 0..1
+    pub fn next(&mut self) -> Float {
+        self.0 = self.0.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        (self.0 >> 40) as Float / (1u64 << 24) as Float
+    }
+
+    pub fn range(&mut self, low: Float, high: Float) -> Float {
+        low + self.next() * (high - low)
+    }
+
+    pub fn vec(&mut self, low: Float, high: Float) -> Vec3f {
+        Vec3f::cons(self.range(low, high), self.range(low, high), self.range(low, high))
+    }
+}
@@ -1,33 +1,188 @@
 #![allow(dead_code)]
 
+use image::error::ParameterError;
+use image::error::ParameterErrorKind;
+use image::ImageError;
+use image::ImageResult;
 use image::open;
 
+use crate::math::Vec2f;
 use crate::render_utils::Color;
 use crate::Float;
 
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Filter {
+    Nearest,
+    Bilinear,
+    Trilinear,
+}
+
+impl Filter {
+    pub fn next(self) -> Filter {
+        match self {
+            Filter::Nearest => Filter::Bilinear,
+            Filter::Bilinear => Filter::Trilinear,
+            Filter::Trilinear => Filter::Nearest,
+        }
+    }
+}
+
+/// applied per texel so bilinear filtering blends across a seam
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum AddressMode {
+    Repeat,
+    MirroredRepeat,
+    ClampToEdge,
+    ClampToBorder(Color),
+}
+
+impl AddressMode {
+    #[inline]
+    fn resolve(self, coord: i64, size: usize) -> Option<usize> {
+        let size = size as i64;
+        let resolved = match self {
+            AddressMode::Repeat => coord.rem_euclid(size),
+            AddressMode::MirroredRepeat => {
+                let period = coord.rem_euclid(2 * size);
+                if period < size {
+                    period
+                }
+                else {
+                    2 * size - 1 - period
+                }
+            }
+            AddressMode::ClampToEdge => coord.clamp(0, size - 1),
+            AddressMode::ClampToBorder(_) => {
+                if coord < 0 || coord >= size {
+                    return None;
+                }
+                coord
+            }
+        };
+        Some(resolved as usize)
+    }
+
+    #[inline]
+    fn border(self) -> Color {
+        match self {
+            AddressMode::ClampToBorder(color) => color,
+            _ => Color::cons_rgba(0, 0, 0, 0),
+        }
+    }
+}
+
+#[derive(Clone)]
+struct MipLevel {
+    height: usize,
+    width: usize,
+    texels: Vec<Color>,
+}
+
+/// images are stored top row first, `flip_v` flips v on lookup for obj's bottom up uvs
+#[derive(Clone)]
 pub struct Texture {
     pub height: usize,
     pub width: usize,
-    texture: Vec<Color>,
+    pub address: AddressMode,
+    pub flip_v: bool,
+    /// set when any texel is less than fully opaque
+    pub translucent: bool,
+    levels: Vec<MipLevel>,
 }
 
 impl Texture {
-    pub fn build_from_file(path: &str) -> Texture {
-        let image = open(path).unwrap().to_rgb8();
+    /// decoded to linear so the mip levels and filters average actual light
+    pub fn build_from_file(path: &str) -> ImageResult<Texture> {
+        Texture::load(path, true)
+    }
+
+    /// for maps that hold data rather than color, like bump maps, kept exactly as stored
+    pub fn build_data_from_file(path: &str) -> ImageResult<Texture> {
+        Texture::load(path, false)
+    }
+
+    fn load(path: &str, srgb: bool) -> ImageResult<Texture> {
+        let image = open(path)?.to_rgba8();
         let (width, height) = image.dimensions();
+        if width == 0 || height == 0 {
+            let kind = ParameterErrorKind::Generic(format!("the image is {}x{}", width, height));
+            return Err(ImageError::Parameter(ParameterError::from_kind(kind)));
+        }
         let data = image.as_raw();
 
         let mut texture = Vec::new();
-        for window in data.chunks(3) {
-            texture.push(Color::cons(window[0], window[1], window[2]));
+        for window in data.chunks(4) {
+            let texel = Color::cons_rgba(window[0], window[1], window[2], window[3]);
+            if srgb {
+                texture.push(texel.decode_srgb());
+            }
+            else {
+                texture.push(texel);
+            }
+        }
+
+        Ok(Texture::cons(height as usize, width as usize, texture))
+    }
+
+    pub fn cons(height: usize, width: usize, texels: Vec<Color>) -> Texture {
+        debug_assert!(texels.len() == height * width);
+        debug_assert!(height > 0 && width > 0);
+
+        let translucent = texels.iter().any(|texel| texel.alpha < 255.);
+        let mut levels = vec![MipLevel { height, width, texels }];
+        while let Some(level) = levels.last().and_then(MipLevel::downsample) {
+            levels.push(level);
         }
 
-        Texture { height: height as usize, width: width as usize, texture }
+        Texture { height, width, address: AddressMode::Repeat, flip_v: true, translucent, levels }
     }
 
     pub fn get_texture(&self, x: Float, y: Float) -> Color {
-        let idx = self.idx(x, y);
-        self.texture[idx]
+        self.levels[0].nearest(x, self.orient_v(y), self.address)
+    }
+
+    /// `lod` is log2 of how many base texels one pixel covers
+    pub fn sample(&self, x: Float, y: Float, lod: Float, filter: Filter) -> Color {
+        let (y, address) = (self.orient_v(y), self.address);
+        let lod = lod.clamp(0., self.max_lod());
+        match filter {
+            Filter::Nearest => self.levels[lod.round() as usize].nearest(x, y, address),
+            Filter::Bilinear => self.levels[lod.round() as usize].bilinear(x, y, address),
+            Filter::Trilinear => {
+                let lower = lod.floor() as usize;
+                let upper = (lower + 1).min(self.levels.len() - 1);
+                let fine = self.levels[lower].bilinear(x, y, address);
+                let coarse = self.levels[upper].bilinear(x, y, address);
+                fine.lerp(&coarse, lod.fract())
+            }
+        }
+    }
+
+    pub fn lod(&self, ddx: Vec2f, ddy: Vec2f) -> Float {
+        let footprint = |delta: Vec2f| {
+            let (x, y) = (delta.x * self.get_width(), delta.y * self.get_height());
+            x * x + y * y
+        };
+        let largest = footprint(ddx).max(footprint(ddy));
+        if largest <= 0. || !largest.is_finite() {
+            return 0.;
+        }
+        // half of log2 of the squared length saves the square root
+        0.5 * largest.log2()
+    }
+
+    pub fn max_lod(&self) -> Float {
+        (self.levels.len() - 1) as Float
+    }
+
+    #[inline]
+    fn orient_v(&self, y: Float) -> Float {
+        if self.flip_v {
+            1. - y
+        }
+        else {
+            y
+        }
     }
 
     #[inline]
@@ -39,21 +194,213 @@ impl Texture {
     fn get_height(&self) -> Float {
         self.height as Float
     }
+}
+
+impl MipLevel {
+    /// odd sizes clamp so the last row or column isn't dropped
+    fn downsample(&self) -> Option<MipLevel> {
+        if self.width == 1 && self.height == 1 {
+            return None;
+        }
+
+        let width = (self.width / 2).max(1);
+        let height = (self.height / 2).max(1);
+        let mut texels = Vec::with_capacity(width * height);
+        for y in 0..height {
+            for x in 0..width {
+                let (x0, y0) = ((x * 2).min(self.width - 1), (y * 2).min(self.height - 1));
+                let (x1, y1) = ((x * 2 + 1).min(self.width - 1), (y * 2 + 1).min(self.height - 1));
+                let top = self.texel(x0, y0).lerp(&self.texel(x1, y0), 0.5);
+                let bottom = self.texel(x0, y1).lerp(&self.texel(x1, y1), 0.5);
+                texels.push(top.lerp(&bottom, 0.5));
+            }
+        }
+
+        Some(MipLevel { height, width, texels })
+    }
 
     #[inline]
-    fn idx(&self, x: Float, y: Float) -> usize {
-        let nx = ((x * self.get_width()) as usize).min(self.width - 1);
-        let ny = ((y * self.get_height()) as usize).min(self.height - 1);
+    fn nearest(&self, x: Float, y: Float, address: AddressMode) -> Color {
+        let nx = (x * self.width as Float).floor() as i64;
+        let ny = (y * self.height as Float).floor() as i64;
+        self.fetch(nx, ny, address)
+    }
 
+    /// texel centers sit at half coordinates
+    #[inline]
+    fn bilinear(&self, x: Float, y: Float, address: AddressMode) -> Color {
+        let fx = x * self.width as Float - 0.5;
+        let fy = y * self.height as Float - 0.5;
+        let (x0, y0) = (fx.floor(), fy.floor());
+        let (tx, ty) = (fx - x0, fy - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+
+        let upper = self.fetch(x0, y0, address).lerp(&self.fetch(x0 + 1, y0, address), tx);
+        let lower = self.fetch(x0, y0 + 1, address).lerp(&self.fetch(x0 + 1, y0 + 1, address), tx);
+        upper.lerp(&lower, ty)
+    }
+
+    #[inline]
+    fn fetch(&self, x: i64, y: i64, address: AddressMode) -> Color {
+        match (address.resolve(x, self.width), address.resolve(y, self.height)) {
+            (Some(x), Some(y)) => self.texel(x, y),
+            _ => address.border(),
+        }
+    }
+
+    #[inline]
+    fn texel(&self, x: usize, y: usize) -> Color {
         {
-            debug_assert!(self.inbounds(nx, ny), "index: {},{} dims: {},{}", nx, ny, self.width, self.height);
+            debug_assert!(x < self.width && y < self.height, "index: {},{} dims: {},{}", x, y, self.width, self.height);
         }
 
-        ny * self.width + nx
+        self.texels[y * self.width + x]
     }
+}
 
-    #[inline]
-    fn inbounds(&self, x: usize, y: usize) -> bool {
-        x < self.width && y < self.height
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::RgbaImage;
+    use std::env::temp_dir;
+
+    /// every texel a different color so a wrong lookup can't land on an equal one
+    fn gradient(width: usize, height: usize) -> Texture {
+        let texel = |idx: usize| Color::cons((idx % width * 20) as Float, (idx / width * 20) as Float, 7.);
+        Texture::cons(height, width, (0..width * height).map(texel).collect())
+    }
+
+    #[test]
+    fn mip_chain_halves_down_to_one_texel() {
+        for ((width, height), sizes) in [
+            ((5, 3), vec![(5, 3), (2, 1), (1, 1)]),
+            ((7, 12), vec![(7, 12), (3, 6), (1, 3), (1, 1)]),
+            ((8, 8), vec![(8, 8), (4, 4), (2, 2), (1, 1)]),
+            ((1, 1), vec![(1, 1)]),
+        ] {
+            let texture = gradient(width, height);
+            let found: Vec<(usize, usize)> = texture.levels.iter().map(|level| (level.width, level.height)).collect();
+            assert_eq!(found, sizes);
+            assert!(texture.levels.iter().all(|level| level.texels.len() == level.width * level.height));
+            assert_eq!(texture.max_lod(), (sizes.len() - 1) as Float);
+        }
+    }
+
+    #[test]
+    fn downsample_is_a_box_filter() {
+        let texels = vec![
+            Color::cons_rgba(0, 0, 0, 255),
+            Color::cons_rgba(100, 40, 8, 255),
+            Color::cons_rgba(20, 80, 16, 0),
+            Color::cons_rgba(200, 0, 24, 255),
+        ];
+        let texture = Texture::cons(2, 2, texels);
+        assert_eq!(texture.levels[1].texels, [Color::cons_rgba(80., 30., 12., 191.25)]);
+    }
+
+    #[test]
+    fn lod_is_log2_of_the_footprint() {
+        let texture = gradient(64, 32);
+        let unit = (Vec2f::cons(1. / 64., 0.), Vec2f::cons(0., 1. / 32.));
+        assert_eq!(texture.lod(unit.0, unit.1), 0.);
+        for scale in [2., 4., 8., 3.] {
+            let lod = texture.lod(Vec2f::cons(scale / 64., 0.), Vec2f::cons(0., 1. / 32.));
+            assert!((lod - (scale as Float).log2()).abs() < 1e-5, "{} gave {}", scale, lod);
+            let lod = texture.lod(Vec2f::cons(0., 0.), Vec2f::cons(scale / 64., scale / 32.));
+            assert!((lod - (scale * std::f32::consts::SQRT_2).log2()).abs() < 1e-5, "{} gave {}", scale, lod);
+        }
+        assert_eq!(texture.lod(Vec2f::cons(0, 0), Vec2f::cons(0, 0)), 0.);
+        assert_eq!(texture.lod(Vec2f::cons(Float::NAN, 0.), Vec2f::cons(0, 0)), 0.);
+    }
+
+    #[test]
+    fn bilinear_at_a_texel_center_is_that_texel() {
+        let texture = gradient(5, 3);
+        let level = &texture.levels[0];
+        for y in 0..3 {
+            for x in 0..5 {
+                let (u, v) = ((x as Float + 0.5) / 5., (y as Float + 0.5) / 3.);
+                assert_eq!(level.bilinear(u, v, AddressMode::Repeat), level.texel(x, y), "texel {},{}", x, y);
+                assert_eq!(level.nearest(u, v, AddressMode::Repeat), level.texel(x, y), "texel {},{}", x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn trilinear_blends_between_levels() {
+        let mut texels = vec![Color::cons_rgba(0, 0, 0, 255); 4];
+        texels[0] = Color::cons_rgba(200, 200, 200, 255);
+        let texture = Texture::cons(2, 2, texels);
+        let (fine, coarse) = (texture.levels[0].texel(0, 0), texture.levels[1].texel(0, 0));
+        let center = |lod| texture.sample(0.25, 0.75, lod, Filter::Trilinear);
+        assert_eq!(center(0.), fine);
+        assert_eq!(center(1.), coarse);
+        assert_eq!(center(0.25), fine.lerp(&coarse, 0.25));
+        assert_eq!(center(7.), coarse);
+    }
+
+    /// pam is one of the few formats that can store an image with no pixels
+    #[test]
+    fn empty_images_are_rejected() {
+        for (width, height) in [(0, 0), (0, 4), (4, 0)] {
+            let path = temp_dir().join(format!("mesh_empty_{}x{}.pam", width, height));
+            RgbaImage::new(width, height).save(&path).unwrap();
+            let error = Texture::build_from_file(&path.to_string_lossy()).err().map(|error| error.to_string());
+            let expected = format!("the image is {}x{}", width, height);
+            assert!(error.as_ref().is_some_and(|error| error.contains(&expected)), "{:?}", error);
+        }
+    }
+
+    #[test]
+    fn address_modes_resolve_far_and_negative_coords() {
+        let border = AddressMode::ClampToBorder(Color::cons(9, 9, 9));
+        let table = [
+            (
+                AddressMode::Repeat,
+                &[
+                    (0, Some(0)),
+                    (3, Some(3)),
+                    (4, Some(0)),
+                    (9, Some(1)),
+                    (-1, Some(3)),
+                    (-4, Some(0)),
+                    (-13, Some(3)),
+                ][..],
+            ),
+            (
+                AddressMode::MirroredRepeat,
+                &[
+                    (0, Some(0)),
+                    (3, Some(3)),
+                    (4, Some(3)),
+                    (5, Some(2)),
+                    (7, Some(0)),
+                    (8, Some(0)),
+                    (21, Some(2)),
+                    (-1, Some(0)),
+                    (-2, Some(1)),
+                    (-5, Some(3)),
+                    (-17, Some(0)),
+                ],
+            ),
+            (AddressMode::ClampToEdge, &[(0, Some(0)), (2, Some(2)), (7, Some(3)), (100, Some(3)), (-5, Some(0))]),
+            (border, &[(0, Some(0)), (3, Some(3)), (4, None), (40, None), (-1, None), (-40, None)]),
+        ];
+
+        for (address, cases) in table {
+            for &(coord, expected) in cases {
+                assert_eq!(address.resolve(coord, 4), expected, "{:?} at {}", address, coord);
+            }
+            // a one texel wide texture only ever has texel 0, or the border outside it
+            for coord in [-7, -1, 0, 5] {
+                let expected = (address != border || coord == 0).then_some(0);
+                assert_eq!(address.resolve(coord, 1), expected, "{:?} at {} of 1", address, coord);
+            }
+        }
+
+        let texture = gradient(4, 4);
+        assert_eq!(texture.levels[0].fetch(-1, 2, border), Color::cons(9, 9, 9));
+        assert_eq!(texture.levels[0].fetch(2, 5, AddressMode::Repeat), texture.levels[0].texel(2, 1));
+        assert_eq!(AddressMode::Repeat.border().alpha, 0.);
     }
 }
@@ -0,0 +1,157 @@
+use std::ops::Range;
+
+use crate::geometry::Tri;
+use crate::Float;
+
+/// triangles stay in submission order so depth ties resolve like they do on one thread
+pub struct TileGrid {
+    pub size: usize,
+    pub columns: usize,
+    pub rows: usize,
+    width: usize,
+    height: usize,
+    bins: Vec<Vec<usize>>,
+}
+
+impl TileGrid {
+    pub fn cons(width: usize, height: usize, size: usize) -> TileGrid {
+        let size = size.max(1);
+        let columns = width.div_ceil(size);
+        let rows = height.div_ceil(size);
+        TileGrid { size, columns, rows, width, height, bins: vec![Vec::new(); columns * rows] }
+    }
+
+    /// padded by a pixel since the edge tracer rounds verts up
+    pub fn bin(&mut self, idx: usize, tri: &Tri) {
+        let (min_x, max_x) = min_max(tri.a.pos.x, tri.b.pos.x, tri.c.pos.x);
+        let (min_y, max_y) = min_max(tri.a.pos.y, tri.b.pos.y, tri.c.pos.y);
+        if max_x < -1. || max_y < -1. || min_x > self.width as Float + 1. || min_y > self.height as Float + 1. {
+            return;
+        }
+
+        let first_column = self.column_of(min_x.floor() - 1.);
+        let last_column = self.column_of(max_x.ceil() + 1.);
+        let first_row = self.row_of(min_y.floor() - 1.);
+        let last_row = self.row_of(max_y.ceil() + 1.);
+        for row in first_row..=last_row {
+            for column in first_column..=last_column {
+                self.bins[row * self.columns + column].push(idx);
+            }
+        }
+    }
+
+    pub fn bin_of(&self, tile: usize) -> &[usize] {
+        &self.bins[tile]
+    }
+
+    pub fn tile_bounds(&self, tile: usize) -> (Range<usize>, Range<usize>) {
+        let (row, column) = (tile / self.columns, tile % self.columns);
+        let x = column * self.size..((column + 1) * self.size).min(self.width);
+        let y = row * self.size..((row + 1) * self.size).min(self.height);
+        (x, y)
+    }
+
+    pub fn len(&self) -> usize {
+        self.bins.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bins.is_empty()
+    }
+
+    fn column_of(&self, x: Float) -> usize {
+        (x.max(0.) as usize / self.size).min(self.columns - 1)
+    }
+
+    fn row_of(&self, y: Float) -> usize {
+        (y.max(0.) as usize / self.size).min(self.rows - 1)
+    }
+}
+
+fn min_max(a: Float, b: Float, c: Float) -> (Float, Float) {
+    (a.min(b).min(c), a.max(b).max(c))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::Vec3f;
+
+    fn tri(a: (Float, Float), b: (Float, Float), c: (Float, Float)) -> Tri {
+        Tri::cons_pos(Vec3f::cons(a.0, a.1, 1.), Vec3f::cons(b.0, b.1, 1.), Vec3f::cons(c.0, c.1, 1.))
+    }
+
+    fn binned_tiles(grid: &TileGrid, idx: usize) -> Vec<usize> {
+        (0..grid.len()).filter(|tile| grid.bin_of(*tile).contains(&idx)).collect()
+    }
+
+    #[test]
+    fn edge_tiles_stop_at_the_frame() {
+        let grid = TileGrid::cons(100, 70, 32);
+        assert_eq!((grid.columns, grid.rows, grid.len()), (4, 3, 12));
+        assert_eq!(grid.tile_bounds(0), (0..32, 0..32));
+        assert_eq!(grid.tile_bounds(3), (96..100, 0..32));
+        assert_eq!(grid.tile_bounds(8), (0..32, 64..70));
+        assert_eq!(grid.tile_bounds(11), (96..100, 64..70));
+
+        let mut covered = vec![0; 100 * 70];
+        for tile in 0..grid.len() {
+            let (x, y) = grid.tile_bounds(tile);
+            for row in y {
+                for column in x.clone() {
+                    covered[row * 100 + column] += 1;
+                }
+            }
+        }
+        assert!(covered.iter().all(|count| *count == 1));
+    }
+
+    #[test]
+    fn tiles_larger_than_the_frame_make_one_tile() {
+        let grid = TileGrid::cons(20, 10, 64);
+        assert_eq!((grid.columns, grid.rows), (1, 1));
+        assert_eq!(grid.tile_bounds(0), (0..20, 0..10));
+    }
+
+    #[test]
+    fn triangles_land_in_every_tile_they_overlap() {
+        let mut grid = TileGrid::cons(100, 70, 32);
+        // well inside tile 5, away from its edges so the padding doesn't reach out
+        grid.bin(0, &tri((40., 40.), (50., 40.), (45., 50.)));
+        // crosses the vertical edge between tiles 0 and 1
+        grid.bin(1, &tri((20., 5.), (40., 5.), (30., 20.)));
+        // within a pixel of the edge, the padding pulls in the neighbour
+        grid.bin(2, &tri((32.5, 40.), (50., 40.), (45., 50.)));
+        // the bottom right corner tile, reaching past the frame
+        grid.bin(3, &tri((97., 66.), (140., 66.), (97., 90.)));
+
+        assert_eq!(binned_tiles(&grid, 0), [5]);
+        assert_eq!(binned_tiles(&grid, 1), [0, 1]);
+        assert_eq!(binned_tiles(&grid, 2), [4, 5]);
+        assert_eq!(binned_tiles(&grid, 3), [11]);
+    }
+
+    #[test]
+    fn triangles_off_screen_are_not_binned() {
+        let mut grid = TileGrid::cons(100, 70, 32);
+        grid.bin(0, &tri((-30., 10.), (-10., 10.), (-20., 30.)));
+        grid.bin(1, &tri((110., 10.), (130., 10.), (120., 30.)));
+        grid.bin(2, &tri((10., 80.), (30., 80.), (20., 95.)));
+        assert!((0..grid.len()).all(|tile| grid.bin_of(tile).is_empty()));
+    }
+
+    #[test]
+    fn bins_keep_submission_order() {
+        let mut grid = TileGrid::cons(64, 64, 16);
+        let cover = tri((-10., -10.), (200., -10.), (-10., 200.));
+        let small = tri((2., 2.), (6., 2.), (2., 6.));
+        for idx in [0, 1, 2] {
+            grid.bin(idx, &cover);
+        }
+        grid.bin(3, &small);
+        grid.bin(4, &cover);
+
+        assert_eq!(grid.bin_of(0), [0, 1, 2, 3, 4]);
+        assert_eq!(grid.bin_of(5), [0, 1, 2, 4]);
+    }
+}
@@ -1,16 +1,28 @@
+use std::process::exit;
+
 use minifb::Key;
+use minifb::KeyRepeat;
 use minifb::MouseButton;
 use minifb::MouseMode;
 use minifb::Scale;
 use minifb::Window;
 use minifb::WindowOptions;
 
+use crate::bounds::CullStats;
+use crate::bounds::Sphere;
+use crate::config::Config;
 use crate::geometry::Mesh;
+use crate::math::Mat4;
 use crate::math::Vec2f;
 use crate::math::Vec3f;
 use crate::render_utils::Buffer;
 use crate::render_utils::Camera;
+use crate::render_utils::Pick;
+use crate::render_utils::Projection;
+use crate::render_utils::Scene;
+use crate::renderer::RenderSettings;
 use crate::renderer::Renderer;
+use crate::Float;
 use crate::PI;
 
 /* keep all this out of main
@@ -24,94 +36,262 @@ literally can't even compile on a RPI5 without pulling this into here */
 
 pub fn make_window(buffer: &Buffer, fps: usize, scale: Scale) -> Window {
     let mut window =
-        Window::new("", buffer.width, buffer.height, WindowOptions { scale, ..Default::default() }).unwrap();
+        Window::new("", buffer.output_width(), buffer.output_height(), WindowOptions { scale, ..Default::default() })
+            .unwrap();
     window.set_target_fps(fps);
     window
 }
 
-#[allow(unused_variables, unused_mut)]
-pub fn make_mesh() -> Mesh {
-    let mut mesh = Mesh::build_from_file_extended("portal/portal.obj", 55., Some("portal/portal_tex.jpg"));
-    mesh.center = Vec3f::cons(0, 0, 0);
-    mesh.rotation.x += PI / 2.;
+pub const MAIN_OBJECT: &str = "model";
+
+/// radius an auto-framed model's bounding sphere gets scaled to
+const FRAMED_RADIUS: Float = 50.;
+
+pub fn make_scene(config: &Config) -> Scene {
+    let mut scene = Scene::new();
+    scene.add(MAIN_OBJECT, make_mesh(config));
+    scene
+}
+
+/// obj files are y up, the world here is z up
+pub fn make_mesh(config: &Config) -> Mesh {
+    let scale = config.scale.unwrap_or(1.);
+    let mut mesh = Mesh::build_from_file(&config.model, scale, config.texture.as_deref()).unwrap_or_else(|error| {
+        eprintln!("{}", error);
+        exit(1);
+    });
+    if config.scale.is_none() {
+        mesh.fit_to(FRAMED_RADIUS);
+    }
+    mesh.transform.position = Vec3f::cons(0, 0, 0);
+    mesh.rotate_x(PI / 2.);
     mesh
 }
 
-pub fn handle_mutation_input(window: &Window, mesh: &mut Mesh, mouse: &mut Option<Vec2f>) {
+/// auto-framed models are backed off until their sphere fits the narrower side
+pub fn make_camera(config: &Config, scene: &Scene) -> Camera {
+    let sphere = scene.get(MAIN_OBJECT).map(|mesh| mesh.sphere).unwrap_or(Sphere::cons(Vec3f::cons(0, 0, 0), 0.));
+    let model = scene.world_matrix(MAIN_OBJECT).unwrap_or(Mat4::identity());
+    let sphere = sphere.transform(&model);
+
+    let aspect = config.height as Float / config.width as Float;
+    let half_fov = ((config.fov / 2.).to_radians().tan() * aspect.min(1.)).atan();
+    let framed = match config.scale {
+        Some(_) => Vec3f::cons(-100, 0, 0),
+        None => sphere.center - Vec3f::cons(sphere.radius / half_fov.sin(), 0., 0.),
+    };
+
+    let position = config.camera.unwrap_or(framed);
+    let mut camera = match config.target {
+        Some(target) => Camera::look_at(position, target),
+        None => Camera::cons(position),
+    };
+    if config.scale.is_none() {
+        camera.ortho_size = sphere.radius * aspect.max(1.);
+    }
+    camera.set_projection(config.projection);
+    camera
+}
+
+/// anything that moves the main object leaves the shadow maps stale
+pub fn handle_mutation_input(window: &Window, scene: &mut Scene, mouse: &mut Option<Vec2f>) {
+    let Some(mesh) = scene.get_mut(MAIN_OBJECT)
+    else {
+        return;
+    };
+
+    let mut moved = false;
     if !window.is_key_down(Key::T) {
         if window.is_key_down(Key::K) {
-            let mut rotation = Vec3f::cons(0., 0., 0.1);
-            rotation.inv_rot_zyx(mesh.rotation);
-            mesh.rotate_z(rotation.z);
-            mesh.rotate_y(rotation.y);
-            mesh.rotate_x(rotation.x);
+            mesh.transform.rotate_world(Vec3f::cons(0, 0, 1), 0.1);
+            moved = true;
         }
     }
     else {
         mesh.rotate_x(0.01);
         mesh.rotate_y(0.005);
         mesh.rotate_z(0.01);
+        moved = true;
     }
 
     if let Some((x, y)) = window.get_mouse_pos(MouseMode::Discard) {
-        if window.get_mouse_down(MouseButton::Left) {
-            if let Some(past_pos) = mouse {
-                let screen_dx = -(past_pos.x - x);
-                let screen_dy = past_pos.y - y;
-
-                mesh.rotate_y(screen_dy * 0.01);
-                mesh.rotate_z(screen_dx * 0.01);
-            }
+        if window.get_mouse_down(MouseButton::Left)
+            && let Some(past_pos) = mouse
+        {
+            let screen_dx = -(past_pos.x - x);
+            let screen_dy = past_pos.y - y;
+
+            mesh.transform.rotate_world(Vec3f::cons(0, 1, 0), screen_dy * 0.01);
+            mesh.transform.rotate_world(Vec3f::cons(0, 0, 1), screen_dx * 0.01);
+            moved = true;
         }
         *mouse = Some(Vec2f::cons(x, y));
     }
     else {
         *mouse = None;
     }
+
+    if moved {
+        scene.invalidate_shadows();
+    }
 }
 
-pub fn handle_renderer_input(window: &Window, mut renderer: Renderer) {
+pub fn render_headless(
+    path: &str, buffer: &mut Buffer, scene: &Scene, camera: &Camera, fov: Float, settings: RenderSettings,
+) {
+    buffer.clear();
+    let mut renderer = Renderer::cons(buffer, scene, camera, fov, settings);
+    renderer.render_scene();
+    let stats = renderer.cull_stats();
+    buffer.resolve();
+
+    if let Err(error) = buffer.save(path) {
+        eprintln!("failed to write '{}': {}", path, error);
+        exit(1);
+    }
+    println!("wrote {}x{} frame to '{}'", buffer.output_width(), buffer.output_height(), path);
+    println!("{}", describe_culling(&stats));
+}
+
+/// `V` steps through the debug views, hands back what got frustum culled
+pub fn handle_renderer_input(
+    window: &Window, mut renderer: Renderer, settings: &mut RenderSettings, picked: Option<&Pick>,
+) -> CullStats {
+    if window.is_key_pressed(Key::V, KeyRepeat::No) {
+        settings.cycle_view();
+        renderer.set_view(settings.view);
+    }
     if !window.is_key_down(Key::P) {
-        renderer.render_mesh();
+        renderer.render_scene();
     }
     if window.is_key_down(Key::O) {
         renderer.render_wireframe();
     }
+    if let Some(pick) = picked {
+        renderer.render_highlight(pick);
+    }
+    renderer.cull_stats()
 }
 
-pub fn handle_camera_input(window: &Window, camera: &mut Camera) {
-    if window.is_key_down(Key::Up) {
-        camera.rotate_horizontal(-0.05);
+/// the last pick sticks around after the right mouse button is let go
+pub fn handle_pick_input(window: &Window, scene: &Scene, camera: &Camera, fov: Float, picked: &mut Option<Pick>) {
+    if !window.get_mouse_down(MouseButton::Right) {
+        return;
     }
-    if window.is_key_down(Key::Down) {
-        camera.rotate_horizontal(0.05);
+    let Some((x, y)) = window.get_mouse_pos(MouseMode::Discard)
+    else {
+        return;
+    };
+    let (width, height) = window.get_size();
+    *picked = scene.pick(&camera.screen_ray(x, y, width as Float, height as Float, fov));
+}
+
+pub fn describe_pick(scene: &Scene, picked: Option<&Pick>) -> String {
+    let Some(pick) = picked
+    else {
+        return "nothing picked".to_string();
+    };
+    let Some(mesh) = scene.get(&pick.object)
+    else {
+        return format!("picked '{}' is gone", pick.object);
+    };
+    let Some(tri) = mesh.tris.get(pick.hit.tri)
+    else {
+        return format!("picked '{}' is gone", pick.object);
+    };
+    let texpos = pick.hit.texpos(tri);
+    let material = mesh.materials.get(tri.material).map_or("", |material| material.name.as_str());
+    format!(
+        "picked '{}' tri {} uv ({:.3}, {:.3}) material '{}'",
+        pick.object, pick.hit.tri, texpos.x, texpos.y, material
+    )
+}
+
+pub fn describe_culling(stats: &CullStats) -> String {
+    format!(
+        "culled {}/{} objects, {}/{} chunks",
+        stats.objects_culled, stats.objects, stats.chunks_culled, stats.chunks
+    )
+}
+
+pub fn handle_settings_input(window: &Window, settings: &mut RenderSettings) {
+    if window.is_key_pressed(Key::I, KeyRepeat::No) {
+        settings.toggle_interpolation();
+    }
+    if window.is_key_pressed(Key::B, KeyRepeat::No) {
+        settings.toggle_raster();
+    }
+    if window.is_key_pressed(Key::L, KeyRepeat::No) {
+        settings.toggle_shading();
+    }
+    if window.is_key_pressed(Key::N, KeyRepeat::No) {
+        settings.toggle_normals();
     }
-    if window.is_key_down(Key::E) {
-        camera.rotate_vertical(-0.05);
+    if window.is_key_pressed(Key::H, KeyRepeat::No) {
+        settings.toggle_shadows();
+    }
+    if window.is_key_pressed(Key::M, KeyRepeat::No) {
+        settings.cycle_filter();
+    }
+    if window.is_key_pressed(Key::G, KeyRepeat::No) {
+        settings.cycle_tone_map();
+    }
+}
+
+/// `X` steps through the projections, `Z` toggles orbiting where `W` and `S` zoom
+pub fn handle_camera_input(window: &Window, camera: &mut Camera, scene: &Scene) {
+    if window.is_key_pressed(Key::X, KeyRepeat::No) {
+        camera.set_projection(camera.projection.next());
+    }
+    if window.is_key_pressed(Key::Z, KeyRepeat::No) {
+        match camera.orbit {
+            Some(_) => camera.orbit = None,
+            None => camera.set_orbit(focus_point(scene)),
+        }
+    }
+    if window.is_key_down(Key::Equal) {
+        camera.zoom(0.97);
+    }
+    if window.is_key_down(Key::Minus) {
+        camera.zoom(1.03);
+    }
+
+    if camera.projection != Projection::Isometric {
+        if window.is_key_down(Key::Up) {
+            camera.rotate_horizontal(-0.05);
+        }
+        if window.is_key_down(Key::Down) {
+            camera.rotate_horizontal(0.05);
+        }
+        if window.is_key_down(Key::E) {
+            camera.rotate_vertical(-0.05);
+        }
+        if window.is_key_down(Key::Q) {
+            camera.rotate_vertical(0.05);
+        }
     }
-    if window.is_key_down(Key::Q) {
-        camera.rotate_vertical(0.05);
+
+    if camera.orbit.is_some() {
+        if window.is_key_down(Key::W) {
+            camera.zoom(0.97);
+        }
+        if window.is_key_down(Key::S) {
+            camera.zoom(1.03);
+        }
+        return;
     }
 
     if window.is_key_down(Key::W) {
-        let mut delta = Vec3f::cons(1, 0, 0);
-        delta.rot_z(camera.rotation.z);
-        camera.position += delta;
+        camera.move_planar(Vec3f::cons(1, 0, 0));
     }
     if window.is_key_down(Key::S) {
-        let mut delta = Vec3f::cons(-1, 0, 0);
-        delta.rot_z(camera.rotation.z);
-        camera.position += delta;
+        camera.move_planar(Vec3f::cons(-1, 0, 0));
     }
     if window.is_key_down(Key::A) {
-        let mut delta = Vec3f::cons(0, 1, 0);
-        delta.rot_z(camera.rotation.z);
-        camera.position += delta;
+        camera.move_planar(Vec3f::cons(0, 1, 0));
     }
     if window.is_key_down(Key::D) {
-        let mut delta = Vec3f::cons(0, -1, 0);
-        delta.rot_z(camera.rotation.z);
-        camera.position += delta;
+        camera.move_planar(Vec3f::cons(0, -1, 0));
     }
 
     if window.is_key_down(Key::R) {
@@ -122,6 +302,16 @@ pub fn handle_camera_input(window: &Window, camera: &mut Camera) {
     }
 }
 
+/// the middle of the main object's bounds in world space
+fn focus_point(scene: &Scene) -> Vec3f {
+    let Some(mesh) = scene.get(MAIN_OBJECT)
+    else {
+        return Vec3f::cons(0, 0, 0);
+    };
+    let model = scene.world_matrix(MAIN_OBJECT).unwrap_or(Mat4::identity());
+    model.transform_point(&mesh.bounds.center())
+}
+
 // const OPTION: bool = false;
 
 // fn gen_range(x: i32) -> Range<i32> {
@@ -135,3 +325,22 @@ pub fn handle_camera_input(window: &Window, camera: &mut Camera) {
 //     }
 //     #[endif]
 // }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bvh::Hit;
+    use crate::geometry::Tri;
+
+    #[test]
+    fn pick_survives_its_mesh_being_replaced() {
+        let tri = Tri::cons_pos(Vec3f::cons(5, -1, -1), Vec3f::cons(5, 1, -1), Vec3f::cons(5, 0, 1));
+        let mut scene = Scene::new();
+        scene.add("model", Mesh::cons(vec![tri, tri], Vec3f::cons(0, 0, 0)));
+        let pick = Pick { object: "model".to_string(), hit: Hit { t: 5., tri: 1, u: 0.2, v: 0.3 } };
+        assert!(describe_pick(&scene, Some(&pick)).starts_with("picked 'model' tri 1"));
+
+        scene.add("model", Mesh::cons(vec![tri], Vec3f::cons(0, 0, 0)));
+        assert_eq!(describe_pick(&scene, Some(&pick)), "picked 'model' is gone");
+    }
+}